@@ -1,25 +1,820 @@
-use anyhow::Result;
-use sga::extract_all;
+mod progress;
 
-use std::path::PathBuf;
+use anyhow::{bail, Context, Result};
+use indicatif::HumanBytes;
+use progress::ProgressObserver;
+use sga::{
+    archive::{SgaArchive, TocLayout},
+    builder::StoredFile,
+    codec::CodecRegistry,
+    delta::{self, DeltaReport},
+    entires::{FileStorageType, FileVerificationType},
+    observer::ExtractObserver,
+    manifest::ManifestFormat,
+    merge::{self, Precedence},
+    packer::{self, PackConfig, PackOptions},
+    patch::{self, ArchivePatcher},
+    plan::ExtractPlan,
+    recompress::{self, StoragePolicy},
+    split::{self, SplitBy},
+    subset,
+    sink::{ExtractSink, MemorySink, TarSink, ZipSink},
+    AtomicMode, ExtractOptions, LinkMode, OverwritePolicy,
+};
 
-use clap::Parser;
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    extract: Option<ExtractArgs>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Extract the files of an archive
+    Extract(ExtractArgs),
+
+    /// List the tables of contents of an archive
+    Tocs {
+        /// Input file path
+        input: PathBuf,
+    },
+
+    /// Pack a folder into a new archive, every folder inside it is a table of contents
+    Pack(PackArgs),
+
+    /// Rebuild an archive byte for byte from a folder it was extracted into with --raw
+    Repack {
+        /// Folder the archive was extracted into
+        input: PathBuf,
+
+        /// Output archive path
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+    },
+
+    /// Write a copy of an archive with its files stored with another storage type
+    Recompress(RecompressArgs),
+
+    /// Write a smaller archive holding only the selected files of an archive
+    Subset(SubsetArgs),
+
+    /// Merge several archives into one
+    Merge {
+        /// Input file paths
+        #[arg(required = true, num_args = 2..)]
+        inputs: Vec<PathBuf>,
+
+        /// Output archive path
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+
+        /// Which archive's file is kept when several archives have a file at the same path
+        #[arg(long, value_enum, default_value_t = MergePrecedence::Last)]
+        precedence: MergePrecedence,
+    },
+
+    /// Write every table of contents or top-level folder of an archive into its own archive
+    Split {
+        /// Input file path
+        input: PathBuf,
+
+        /// Output folder path
+        #[arg(short, long, value_name = "FOLDER")]
+        output: PathBuf,
+
+        /// Split every top-level folder off instead of every table of contents
+        #[arg(long)]
+        by_folder: bool,
+
+        /// Only split this top-level folder off, the other files of its table of contents stay together,
+        /// can be given multiple times. Implies --by-folder
+        #[arg(long = "folder", value_name = "NAME")]
+        folders: Vec<String>,
+    },
+
+    /// Replace, add or remove files of an archive in place, appending the new data to it
+    Patch(PatchArgs),
+
+    /// Rewrite an archive in place without the data patching left behind
+    Compact {
+        /// Archive path
+        input: PathBuf,
+    },
+
+    /// Write a delta archive holding the files which changed between two versions of an archive
+    Delta {
+        /// Old archive path
+        old: PathBuf,
+
+        /// New archive path
+        new: PathBuf,
+
+        /// Output delta archive path
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+    },
+
+    /// Apply a delta archive to the old version of an archive, writing the new version
+    ApplyDelta {
+        /// Old archive path
+        input: PathBuf,
+
+        /// Delta archive path
+        delta: PathBuf,
+
+        /// Output archive path
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+    },
+}
+
+#[derive(Args)]
+struct ExtractArgs {
+    /// Input file path
+    input: PathBuf,
+
+    /// Output folder path, or output file path for tar and zip. Use '-' to write a tar to stdout
+    #[arg(short, long, value_name = "FILE")]
+    output: PathBuf,
+
+    /// How the extracted files are written
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Dir)]
+    format: OutputFormat,
+
+    #[command(flatten)]
+    selection: SelectionArgs,
+
+    /// Extract every table of contents into the output itself, instead of a folder named after its alias
+    #[arg(long)]
+    merge_tocs: bool,
+
+    /// Write all extracted files into a single folder per table of contents
+    #[arg(long)]
+    flatten: bool,
+
+    /// What to do with files which already exist in the output, or have the same path as another file when case is ignored
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = Conflict::Overwrite)]
+    overwrite: Conflict,
+
+    /// Protect the output folder from being seen partially written: 'files' writes each file to a temporary file first,
+    /// 'staged' extracts into a hidden folder next to the output and then replaces the output with it
+    #[arg(long, value_enum, value_name = "MODE")]
+    atomic: Option<Atomic>,
+
+    /// Continue an interrupted extraction into the same folder, without extracting the files it completed again
+    #[arg(long)]
+    resume: bool,
+
+    /// Write a manifest with the archive metadata of the extracted files into the output, for repacking them
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    manifest: Option<Manifest>,
+
+    /// Write the files as they're stored in the archive, without decompressing them, and record the layout of the archive
+    /// in the manifest so it can be rebuilt with 'repack'. Writes a JSON manifest unless --manifest is given
+    #[arg(long)]
+    raw: bool,
+
+    /// Decode the data several files share once, and write the other files as links to the first one when extracting into a folder.
+    /// 'reflink' falls back to copying the first file where the file system can't share its blocks
+    #[arg(long, value_enum, value_name = "MODE")]
+    link_shared: Option<Link>,
+
+    /// Only print the files which would be extracted and their sizes, without reading or writing anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Number of threads decoding files
+    #[arg(short, long, value_name = "N", default_value_t = 1)]
+    jobs: usize,
+}
+
+/// Which files of an archive a command works on.
+#[derive(Args)]
+struct SelectionArgs {
+    /// Only select the table of contents with this alias or name, can be given multiple times
+    #[arg(long = "toc", value_name = "ALIAS")]
+    tocs: Vec<String>,
+
+    /// Only select this folder of each table of contents, for example 'art/ui'
+    #[arg(long, value_name = "PATH")]
+    subtree: Option<String>,
+
+    /// How many levels of folders below the selected folder to descend into, 0 only selects the files directly inside it
+    #[arg(long, value_name = "DEPTH")]
+    max_depth: Option<usize>,
+
+    /// Only select files whose path inside their table of contents matches this glob, can be given multiple times
+    #[arg(long = "include", value_name = "GLOB")]
+    includes: Vec<String>,
+
+    /// Skip files whose path inside their table of contents matches this glob, can be given multiple times
+    #[arg(long = "exclude", value_name = "GLOB")]
+    excludes: Vec<String>,
+}
+
+impl SelectionArgs {
+    /// Sets the selection on `options`.
+    fn apply(self, mut options: ExtractOptions) -> ExtractOptions {
+        options = options.tocs(self.tocs);
+        if let Some(subtree) = self.subtree {
+            options = options.subtree(subtree);
+        }
+        if let Some(max_depth) = self.max_depth {
+            options = options.max_depth(max_depth);
+        }
+        for pattern in self.includes {
+            options = options.include(pattern);
+        }
+        for pattern in self.excludes {
+            options = options.exclude(pattern);
+        }
+        options
+    }
+}
+
+#[derive(Args)]
+struct SubsetArgs {
     /// Input file path
     input: PathBuf,
 
-    /// Output folder path
+    /// Output archive path
+    #[arg(short, long, value_name = "FILE")]
+    output: PathBuf,
+
+    #[command(flatten)]
+    selection: SelectionArgs,
+}
+
+#[derive(Args)]
+struct RecompressArgs {
+    /// Input file path
+    input: PathBuf,
+
+    /// Output archive path
+    #[arg(short, long, value_name = "FILE")]
+    output: PathBuf,
+
+    /// Storage type of the files no other rule applies to, they keep their storage type if it isn't given
+    #[arg(long, value_enum, value_name = "STORAGE")]
+    storage: Option<Storage>,
+
+    /// Storage type of the files with an extension, for example 'dds=store', can be given multiple times
+    #[arg(long = "extension", value_name = "EXT=STORAGE", value_parser = parse_rule::<String>)]
+    extensions: Vec<(String, Storage)>,
+
+    /// Storage type of the files smaller than a size in bytes once decoded, for example '4096=store', can be given multiple times
+    #[arg(long = "smaller-than", value_name = "SIZE=STORAGE", value_parser = parse_rule::<u64>)]
+    sizes: Vec<(u64, Storage)>,
+
+    /// Compression level, from 0 to 9 for deflate and 0 to 11 for brotli
+    #[arg(long)]
+    level: Option<u32>,
+}
+
+#[derive(Args)]
+struct PackArgs {
+    /// Input folder path
+    input: PathBuf,
+
+    /// Output archive path
     #[arg(short, long, value_name = "FILE")]
     output: PathBuf,
+
+    /// Name of the archive, the name of the output file without its extension by default.
+    /// The manifest of an extraction in the input folder takes precedence
+    #[arg(long)]
+    name: Option<String>,
+
+    /// JSON or TOML file with the default storage type, level and verification type,
+    /// and rules setting them for the files matching glob patterns
+    #[arg(long, value_name = "FILE", conflicts_with = "profile")]
+    config: Option<PathBuf>,
+
+    /// Pack the files like an official archive of a game, each file extension getting the storage
+    /// and verification type most of its files have in that archive
+    #[arg(long, value_name = "ARCHIVE")]
+    profile: Option<PathBuf>,
+
+    /// Storage type of the files no rule or manifest sets one for, uncompressed by default
+    #[arg(long, value_enum, value_name = "STORAGE")]
+    storage: Option<Storage>,
+
+    /// Compression level of the files no rule sets one for, from 0 to 9 for deflate and 0 to 11 for brotli
+    #[arg(long)]
+    level: Option<u32>,
+
+    /// Number of threads compressing files, the archive is the same whatever the number
+    #[arg(short, long, value_name = "N", default_value_t = 1)]
+    jobs: usize,
+
+    /// Maximum size in MiB of the files read and compressed ahead of the file being written, when several threads compress them
+    #[arg(long, value_name = "MIB", default_value_t = 256)]
+    buffer: u64,
+}
+
+#[derive(Args)]
+struct PatchArgs {
+    /// Archive path
+    input: PathBuf,
+
+    /// Replace a file with a file on disk, for example 'attrib/ebps/villager.rgd=./villager.rgd',
+    /// where the path starts with the alias of the table of contents. Can be given multiple times
+    #[arg(long = "replace", value_name = "PATH=FILE", value_parser = parse_patched_file)]
+    replaced: Vec<(String, String, PathBuf)>,
+
+    /// Add a file on disk at a path starting with the alias of the table of contents, can be given multiple times
+    #[arg(long = "add", value_name = "PATH=FILE", value_parser = parse_patched_file)]
+    added: Vec<(String, String, PathBuf)>,
+
+    /// Remove the file at a path starting with the alias of the table of contents, can be given multiple times
+    #[arg(long = "remove", value_name = "PATH", value_parser = parse_archive_path)]
+    removed: Vec<(String, String)>,
+
+    /// Storage type of the new data. Replaced files keep their storage type and added files are stored uncompressed if it isn't given
+    #[arg(long, value_enum, value_name = "STORAGE")]
+    storage: Option<Storage>,
+
+    /// Compression level, from 0 to 9 for deflate and 0 to 11 for brotli
+    #[arg(long)]
+    level: Option<u32>,
+}
+
+/// Parses a `TOC/PATH` of the patch command into the alias and the path inside the table of contents.
+fn parse_archive_path(value: &str) -> Result<(String, String), String> {
+    match value.split_once(['/', '\\']) {
+        Some((toc, path)) if !toc.is_empty() && !path.is_empty() => Ok((toc.to_string(), path.to_string())),
+        _ => Err(format!("expected the alias of a table of contents followed by a path, got '{}'", value)),
+    }
+}
+
+/// Parses a `TOC/PATH=FILE` of the patch command.
+fn parse_patched_file(value: &str) -> Result<(String, String, PathBuf), String> {
+    let (path, file) = value.split_once('=').ok_or_else(|| format!("expected PATH=FILE, got '{}'", value))?;
+    let (toc, path) = parse_archive_path(path)?;
+    Ok((toc, path, PathBuf::from(file)))
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Storage {
+    /// Uncompressed
+    Store,
+    /// Deflate, stream compressed
+    Stream,
+    /// Deflate, buffer compressed
+    Buffer,
+    /// Brotli, stream compressed
+    BrotliStream,
+    /// Brotli, buffer compressed
+    BrotliBuffer,
+}
+
+impl From<Storage> for FileStorageType {
+    fn from(value: Storage) -> Self {
+        match value {
+            Storage::Store => FileStorageType::Store,
+            Storage::Stream => FileStorageType::StreamCompress,
+            Storage::Buffer => FileStorageType::BufferCompress,
+            Storage::BrotliStream => FileStorageType::StreamCompressBrotli,
+            Storage::BrotliBuffer => FileStorageType::BufferCompressBrotli,
+        }
+    }
+}
+
+/// Parses a `KEY=STORAGE` rule of the recompress command.
+fn parse_rule<T: FromStr>(rule: &str) -> Result<(T, Storage), String> {
+    let (key, storage) = rule.split_once('=').ok_or_else(|| format!("expected KEY=STORAGE, got '{}'", rule))?;
+    let key = key.parse().map_err(|_| format!("invalid value '{}'", key))?;
+    let storage = Storage::from_str(storage, true)?;
+    Ok((key, storage))
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum MergePrecedence {
+    /// The file of the archive given last
+    Last,
+    /// The file of the archive given first
+    First,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    /// A folder on disk
+    Dir,
+    /// A tar archive
+    Tar,
+    /// A zip file
+    Zip,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Conflict {
+    /// Replace the other file
+    Overwrite,
+    /// Keep the other file and don't extract this one
+    Skip,
+    /// Extract the file next to the other one, with a ' (n)' suffix
+    Rename,
+    /// Stop before extracting anything
+    Error,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Manifest {
+    Json,
+    Toml,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Link {
+    /// Hard links to the first file
+    Hardlink,
+    /// Copies of the first file sharing its blocks on disk
+    Reflink,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Atomic {
+    /// Write each file to a temporary file and rename it into place
+    Files,
+    /// Extract into a staging folder and rename it into place
+    Staged,
+}
+
+impl From<Conflict> for OverwritePolicy {
+    fn from(value: Conflict) -> Self {
+        match value {
+            Conflict::Overwrite => OverwritePolicy::Overwrite,
+            Conflict::Skip => OverwritePolicy::Skip,
+            Conflict::Rename => OverwritePolicy::Rename,
+            Conflict::Error => OverwritePolicy::Error,
+        }
+    }
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
-    extract_all(cli.input, cli.output)?;
 
+    match (cli.command, cli.extract) {
+        (Some(Command::Extract(args)), _) | (None, Some(args)) => extract(args),
+        (Some(Command::Tocs { input }), _) => list_tocs(&input),
+        (Some(Command::Pack(args)), _) => pack(args),
+        (Some(Command::Repack { input, output }), _) => sga::pack::repack_raw(&input, &output),
+        (Some(Command::Recompress(args)), _) => recompress(args),
+        (Some(Command::Subset(args)), _) => subset(args),
+        (Some(Command::Merge { inputs, output, precedence }), _) => merge(&inputs, &output, precedence),
+        (Some(Command::Split { input, output, by_folder, folders }), _) => {
+            let by = match by_folder || !folders.is_empty() {
+                true => SplitBy::Folder(folders),
+                false => SplitBy::Toc,
+            };
+            split(&input, &output, &by)
+        }
+        (Some(Command::Patch(args)), _) => patch(args),
+        (Some(Command::Compact { input }), _) => compact(&input),
+        (Some(Command::Delta { old, new, output }), _) => create_delta(&old, &new, &output),
+        (Some(Command::ApplyDelta { input, delta, output }), _) => apply_delta(&input, &delta, &output),
+        (None, None) => {
+            use clap::CommandFactory;
+            Cli::command().print_help()?;
+            Ok(())
+        }
+    }
+}
+
+fn extract(args: ExtractArgs) -> Result<()> {
+    let progress = Arc::new(ProgressObserver::new(args.raw));
+    let mut options = args
+        .selection
+        .apply(ExtractOptions::new(&args.output))
+        .toc_layout(match args.merge_tocs {
+            true => TocLayout::Merged,
+            false => TocLayout::Subdirectory,
+        })
+        .flatten(args.flatten)
+        .overwrite(args.overwrite.into())
+        .atomic(match args.atomic {
+            None => AtomicMode::None,
+            Some(Atomic::Files) => AtomicMode::PerFile,
+            Some(Atomic::Staged) => AtomicMode::Staged,
+        })
+        .resume(args.resume)
+        .raw(args.raw)
+        .link_shared(match args.link_shared {
+            None => LinkMode::None,
+            Some(Link::Hardlink) => LinkMode::Hardlink,
+            Some(Link::Reflink) => LinkMode::Reflink,
+        })
+        .dry_run(args.dry_run)
+        .jobs(args.jobs);
+
+    if !args.dry_run {
+        options = options.observer(progress.clone());
+    }
+    match args.manifest {
+        Some(Manifest::Json) => options = options.manifest(ManifestFormat::Json),
+        Some(Manifest::Toml) => options = options.manifest(ManifestFormat::Toml),
+        None if args.raw => options = options.manifest(ManifestFormat::Json),
+        None => {}
+    }
+
+    if (args.atomic.is_some() || args.resume) && !matches!(args.format, OutputFormat::Dir) {
+        bail!("--atomic and --resume can only be used when extracting into a folder");
+    }
+
+    let mut archive = SgaArchive::open(&args.input).inspect_err(|err| progress.error(None, err))?;
+    let report = match args.format {
+        OutputFormat::Dir => archive.extract(&options)?,
+        _ if args.dry_run => archive.extract_to_sink(&mut MemorySink::new(), &options)?,
+        format => archive.extract_to_sink(open_sink(&args.output, format)?.as_mut(), &options)?,
+    };
+
+    match args.dry_run {
+        true => print_plan(&report.plan),
+        false => progress.finish(),
+    }
+
+    if report.linked > 0 {
+        eprintln!("{} files share their data with another file and were linked to it", report.linked);
+    }
+
+    if report.resumed > 0 {
+        eprintln!("Resumed, {} files were already extracted", report.resumed);
+    }
+
+    if !report.conflicts.is_empty() {
+        eprintln!("{} conflicts:", report.conflicts.len());
+        for conflict in &report.conflicts {
+            eprintln!("  {}", conflict);
+        }
+    }
+
+    Ok(())
+}
+
+fn pack(args: PackArgs) -> Result<()> {
+    let mut config = match (&args.config, &args.profile) {
+        (Some(path), _) => PackConfig::read(path)?,
+        (None, Some(path)) => SgaArchive::open(path)
+            .and_then(|archive| PackConfig::from_archive(&archive))
+            .with_context(|| format!("Failed to read the profile of '{}'", path.display()))?,
+        (None, None) => PackConfig::default(),
+    };
+    if let Some(storage) = args.storage {
+        config.storage_type = storage.into();
+    }
+    if args.level.is_some() {
+        config.level = args.level;
+    }
+
+    let name = args.name.unwrap_or_else(|| args.output.file_stem().unwrap_or_default().to_string_lossy().into_owned());
+    let options = PackOptions::new(name).config(config).jobs(args.jobs).buffer_size(args.buffer * 1024 * 1024);
+    let report = packer::pack_folder(&args.input, BufWriter::new(File::create(&args.output)?), &options)?;
+
+    println!(
+        "{} files in {} tables of contents, {} packed into {} of data{}",
+        report.files,
+        report.tocs,
+        HumanBytes(report.total_bytes),
+        HumanBytes(report.stored_bytes),
+        shared(report.shared_bytes)
+    );
+
+    Ok(())
+}
+
+fn recompress(args: RecompressArgs) -> Result<()> {
+    let mut policy = match args.storage {
+        Some(storage) => StoragePolicy::new(storage.into()),
+        None => StoragePolicy::keep(),
+    };
+    for (extension, storage) in args.extensions {
+        policy = policy.extension(extension, storage.into());
+    }
+    for (size, storage) in args.sizes {
+        policy = policy.smaller_than(size, storage.into());
+    }
+    policy = policy.level(args.level);
+
+    check_not_input(&args.input, &args.output)?;
+    let mut archive = SgaArchive::open(&args.input)?;
+    let report = recompress::recompress(&mut archive, BufWriter::new(File::create(&args.output)?), &policy)?;
+
+    println!(
+        "{} files, {} re-encoded, data went from {} to {}{}",
+        report.files,
+        report.reencoded,
+        HumanBytes(report.stored_bytes_before),
+        HumanBytes(report.stored_bytes_after),
+        shared(report.shared_bytes)
+    );
+
+    Ok(())
+}
+
+fn subset(args: SubsetArgs) -> Result<()> {
+    let options = args.selection.apply(ExtractOptions::default());
+
+    check_not_input(&args.input, &args.output)?;
+    let mut archive = SgaArchive::open(&args.input)?;
+    let report = subset::subset(&mut archive, BufWriter::new(File::create(&args.output)?), &options)?;
+
+    println!(
+        "{} files in {} tables of contents, {} of data{}",
+        report.files,
+        report.tocs,
+        HumanBytes(report.stored_bytes),
+        shared(report.shared_bytes)
+    );
 
     Ok(())
 }
+
+fn merge(inputs: &[PathBuf], output: &Path, precedence: MergePrecedence) -> Result<()> {
+    let mut archives = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        check_not_input(input, output)?;
+        archives.push(SgaArchive::open(input)?);
+    }
+
+    let precedence = match precedence {
+        MergePrecedence::Last => Precedence::Last,
+        MergePrecedence::First => Precedence::First,
+    };
+    let report = merge::merge(&mut archives, BufWriter::new(File::create(output)?), precedence)?;
+
+    for item in &report.overrides {
+        println!(
+            "{}/{}: '{}' overrides '{}'",
+            item.toc,
+            item.path,
+            inputs[item.kept].display(),
+            inputs[item.replaced].display()
+        );
+    }
+    println!(
+        "{} files in {} tables of contents, {} overrides, {} of data{}",
+        report.files,
+        report.tocs,
+        report.overrides.len(),
+        HumanBytes(report.stored_bytes),
+        shared(report.shared_bytes)
+    );
+
+    Ok(())
+}
+
+fn split(input: &Path, output: &Path, by: &SplitBy) -> Result<()> {
+    let mut archive = SgaArchive::open(input)?;
+    let parts = split::split(&mut archive, output, by)?;
+
+    for part in &parts {
+        println!("{}\t{} files, {}{}", part.path.display(), part.files, HumanBytes(part.stored_bytes), shared(part.shared_bytes));
+    }
+
+    Ok(())
+}
+
+fn patch(args: PatchArgs) -> Result<()> {
+    let codecs = CodecRegistry::default();
+    let mut patcher = ArchivePatcher::open(&args.input)?;
+
+    for (toc, path, file) in &args.replaced {
+        let Some(entry) = patcher.file_entry(toc, path) else {
+            bail!("There is no file '{}/{}' to replace", toc, path);
+        };
+        let storage_type = args.storage.map_or(entry.storage_type, FileStorageType::from);
+        let data = fs::read(file).with_context(|| format!("Failed to read '{}'", file.display()))?;
+        let stored = StoredFile::encode(&data, storage_type, entry.verification_type, &codecs, args.level)?;
+        patcher.replace(toc, path, &stored)?;
+    }
+    for (toc, path, file) in &args.added {
+        let storage_type = args.storage.map_or(FileStorageType::Store, FileStorageType::from);
+        let data = fs::read(file).with_context(|| format!("Failed to read '{}'", file.display()))?;
+        let stored = StoredFile::encode(&data, storage_type, FileVerificationType::CRC, &codecs, args.level)?;
+        patcher.add(toc, path, &stored)?;
+    }
+    for (toc, path) in &args.removed {
+        patcher.remove(toc, path)?;
+    }
+
+    let report = patcher.commit()?;
+    println!(
+        "{} replaced, {} added, {} removed, {} appended, {} of dead space",
+        report.replaced,
+        report.added,
+        report.removed,
+        HumanBytes(report.appended_bytes),
+        HumanBytes(report.dead_bytes)
+    );
+
+    Ok(())
+}
+
+fn compact(input: &Path) -> Result<()> {
+    let report = patch::compact(input)?;
+    println!("{} went from {} to {}", input.display(), HumanBytes(report.bytes_before), HumanBytes(report.bytes_after));
+    Ok(())
+}
+
+fn create_delta(old: &Path, new: &Path, output: &Path) -> Result<()> {
+    check_not_input(old, output)?;
+    check_not_input(new, output)?;
+    let mut old = SgaArchive::open(old)?;
+    let mut new = SgaArchive::open(new)?;
+    let report = delta::create_delta(&mut old, &mut new, BufWriter::new(File::create(output)?))?;
+
+    print_delta(&report);
+    Ok(())
+}
+
+fn apply_delta(input: &Path, delta: &Path, output: &Path) -> Result<()> {
+    check_not_input(input, output)?;
+    check_not_input(delta, output)?;
+    let mut archive = SgaArchive::open(input)?;
+    let mut delta = SgaArchive::open(delta)?;
+    let report = delta::apply_delta(&mut archive, &mut delta, BufWriter::new(File::create(output)?))?;
+
+    print_delta(&report);
+    Ok(())
+}
+
+fn print_delta(report: &DeltaReport) {
+    println!(
+        "{} added, {} changed, {} removed, {} unchanged, {} of data{}",
+        report.added,
+        report.changed,
+        report.removed,
+        report.unchanged,
+        HumanBytes(report.stored_bytes),
+        shared(report.shared_bytes)
+    );
+}
+
+/// How much identical data the written archive shares between its files, if it shares any.
+fn shared(bytes: u64) -> String {
+    match bytes {
+        0 => String::new(),
+        bytes => format!(", {} saved by sharing identical data", HumanBytes(bytes)),
+    }
+}
+
+/// Fails if `output` is the `input` archive, which would be destroyed before being read.
+fn check_not_input(input: &Path, output: &Path) -> Result<()> {
+    if output.exists() && output.canonicalize()? == input.canonicalize()? {
+        bail!("The output can not be the input archive itself");
+    }
+    Ok(())
+}
+
+fn list_tocs(input: &Path) -> Result<()> {
+    let archive = SgaArchive::open(input)?;
+
+    for toc in archive.tocs() {
+        println!("{}\t{}\t{} files", toc.alias, toc.name, toc.file_count());
+    }
+
+    Ok(())
+}
+
+/// Prints the files a dry run would have extracted, with their sizes.
+fn print_plan(plan: &ExtractPlan) {
+    for planned in &plan.files {
+        println!("{:>12}  {}", planned.file.size(), planned.path.display());
+    }
+
+    println!(
+        "{} files, {} ({} bytes), {} stored",
+        plan.files.len(),
+        HumanBytes(plan.total_bytes()),
+        plan.total_bytes(),
+        HumanBytes(plan.stored_bytes())
+    );
+}
+
+/// Creates the sink for the tar and zip output formats, folders are written by the archive itself.
+fn open_sink(output: &Path, format: OutputFormat) -> Result<Box<dyn ExtractSink>> {
+    let to_stdout = output == Path::new("-");
+
+    Ok(match format {
+        OutputFormat::Dir => unreachable!("folders are extracted with SgaArchive::extract"),
+        OutputFormat::Tar if to_stdout => Box::new(TarSink::new(BufWriter::new(io::stdout().lock()))),
+        OutputFormat::Tar => Box::new(TarSink::new(BufWriter::new(File::create(output)?))),
+        // The entries of a zip file are patched once written, which stdout doesn't allow.
+        OutputFormat::Zip if to_stdout => bail!("A zip file can not be written to stdout, use --format tar instead"),
+        OutputFormat::Zip => Box::new(ZipSink::new(BufWriter::new(File::create(output)?))),
+    })
+}
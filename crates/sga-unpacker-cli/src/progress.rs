@@ -0,0 +1,89 @@
+use std::{
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
+
+use indicatif::{HumanBytes, HumanDuration, ProgressBar, ProgressStyle};
+use sga::observer::{ArchiveSummary, ExtractObserver};
+
+const PROGRESS_TEMPLATE: &str =
+    "{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta}) {msg}";
+
+/// Draws a progress bar on stderr while an archive is extracted.
+pub struct ProgressObserver {
+    bar: ProgressBar,
+    raw: bool,
+    started: Instant,
+    files: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl ProgressObserver {
+    /// Creates the progress bar, counting the files as stored in the archive if they're extracted `raw`.
+    pub fn new(raw: bool) -> Self {
+        let bar = ProgressBar::new(0);
+        bar.set_style(
+            ProgressStyle::with_template(PROGRESS_TEMPLATE)
+                .expect("progress template is valid")
+                .progress_chars("#>-"),
+        );
+
+        Self {
+            bar,
+            raw,
+            started: Instant::now(),
+            files: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+        }
+    }
+
+    /// Clears the progress bar and prints how much was extracted, and how fast.
+    pub fn finish(&self) {
+        self.bar.finish_and_clear();
+
+        let elapsed = self.started.elapsed();
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        let bytes_per_sec = (bytes as f64 / elapsed.as_secs_f64().max(f64::EPSILON)) as u64;
+
+        // Printed to stderr, stdout may be carrying the extracted files
+        eprintln!(
+            "Extracted {} files ({}) in {}, {}/s",
+            self.files.load(Ordering::Relaxed),
+            HumanBytes(bytes),
+            HumanDuration(elapsed),
+            HumanBytes(bytes_per_sec),
+        );
+    }
+}
+
+impl ExtractObserver for ProgressObserver {
+    fn archive_opened(&self, archive: &ArchiveSummary) {
+        // Raw extraction writes the stored data, whose size isn't the decoded size.
+        self.bar.inc_length(match self.raw {
+            true => archive.stored_bytes,
+            false => archive.total_bytes,
+        });
+    }
+
+    fn file_started(&self, path: &Path, _size: u64) {
+        self.bar.set_message(path.display().to_string());
+    }
+
+    fn file_finished(&self, _path: &Path, bytes_written: u64) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes_written, Ordering::Relaxed);
+        self.bar.inc(bytes_written);
+    }
+
+    fn warning(&self, path: Option<&Path>, message: &str) {
+        match path {
+            Some(path) => self.bar.println(format!("warning: '{}': {}", path.display(), message)),
+            None => self.bar.println(format!("warning: {}", message)),
+        }
+    }
+
+    fn error(&self, _path: Option<&Path>, _error: &anyhow::Error) {
+        self.bar.abandon();
+    }
+}
@@ -0,0 +1,255 @@
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{BufRead, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::{
+    entires::SgaEntries,
+    extract::{run_plan, ExtractReport},
+    journal::{skip_completed, Journal},
+    layout::ArchiveLayout,
+    manifest::{Manifest, ManifestFormat},
+    nodes::{FolderNode, Node, Toc},
+    observer::ArchiveSummary,
+    options::{AtomicMode, ExtractOptions},
+    plan::ExtractPlan,
+    sink::{DirectorySink, ExtractSink},
+    staging::StagingDirectory,
+};
+
+/// How the tables of contents of an archive are laid out when extracted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TocLayout {
+    /// Every table of contents is extracted into a folder named after its alias.
+    #[default]
+    Subdirectory,
+
+    /// Every table of contents is extracted into the root of the output, their files may collide.
+    Merged,
+}
+
+/// An opened SGA archive, with the folder tree of every table of contents read.
+#[derive(Debug)]
+pub struct SgaArchive<R = BufReader<File>> {
+    reader: R,
+
+    /// The parsed entries of the archive.
+    pub entries: SgaEntries,
+
+    tocs: Vec<Toc>,
+}
+
+impl SgaArchive<BufReader<File>> {
+    /// Opens the archive at `path` and reads its folder tree.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + BufRead + Seek> SgaArchive<R> {
+    /// Reads an archive and its folder tree from `reader`.
+    pub fn from_reader(mut reader: R) -> Result<Self> {
+        let entries = SgaEntries::new(&mut reader)?;
+
+        let mut tocs = Vec::with_capacity(entries.tocs.len());
+        for toc_entry in &entries.tocs {
+            let toc = Toc::initialize_from_entry(&mut reader, &entries, toc_entry.clone())?;
+            visit_folder(&mut reader, toc.root_folder.clone(), &entries)?;
+            tocs.push(toc);
+        }
+
+        Ok(Self { reader, entries, tocs })
+    }
+
+    /// The tables of contents of the archive.
+    pub fn tocs(&self) -> &[Toc] {
+        &self.tocs
+    }
+
+    /// Finds a table of contents by its alias or name, ignoring case.
+    pub fn toc(&self, alias_or_name: &str) -> Option<&Toc> {
+        self.tocs.iter().find(|toc| toc.matches(alias_or_name))
+    }
+
+    /// The reader the archive is read from, used to read file data.
+    pub fn reader(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Reads the layout of the archive, everything besides the data of its files.
+    pub fn layout(&mut self) -> Result<ArchiveLayout> {
+        ArchiveLayout::read(&mut self.reader, &self.entries)
+    }
+
+    /// Reads the data of the file entry at `index` as it's stored in the archive.
+    pub fn read_stored(&mut self, index: usize) -> Result<Vec<u8>> {
+        let Some(entry) = self.entries.files.get(index) else {
+            bail!("The archive has no file entry {}", index);
+        };
+
+        let mut stored = vec![0; entry.compressed_length as usize];
+        self.reader.seek(SeekFrom::Start(self.entries.header.data_offset + entry.data_offset))?;
+        self.reader
+            .read_exact(&mut stored)
+            .with_context(|| format!("Failed to read the data of file entry {}", index))?;
+
+        Ok(stored)
+    }
+
+    /// Returns the selected tables of contents, in archive order.
+    /// An empty selection selects every table of contents.
+    pub fn select_tocs<S: AsRef<str>>(&self, selection: &[S]) -> Result<Vec<&Toc>> {
+        if let Some(missing) = selection.iter().find(|wanted| self.toc(wanted.as_ref()).is_none()) {
+            let available: Vec<_> = self.tocs.iter().map(|toc| toc.alias.as_str()).collect();
+            bail!(
+                "No table of contents with alias or name '{}', available are: {}",
+                missing.as_ref(),
+                available.join(", ")
+            );
+        }
+
+        Ok(self
+            .tocs
+            .iter()
+            .filter(|toc| selection.is_empty() || selection.iter().any(|wanted| toc.matches(wanted.as_ref())))
+            .collect())
+    }
+
+    /// Plans the extraction of the tables of contents, folders and files selected in `options`.
+    pub fn plan(&self, options: &ExtractOptions) -> Result<ExtractPlan> {
+        let tocs = self.select_tocs(&options.tocs)?;
+        ExtractPlan::for_tocs(&tocs, options)
+    }
+
+    /// Extracts what is selected in `options` into the folder at its output path.
+    ///
+    /// Unless the extraction is staged, completed files are recorded in a journal in the output folder,
+    /// which lets `ExtractOptions::resume` continue an interrupted extraction. It's only created once the files are planned
+    /// and their conflicts resolved, and removed once the extraction succeeds or if it fails before completing any file.
+    pub fn extract(&mut self, options: &ExtractOptions) -> Result<ExtractReport> {
+        if options.dry_run {
+            let completed = match options.resume {
+                true => Journal::read(&options.output, &self.entries.header)?.unwrap_or_default(),
+                false => HashSet::new(),
+            };
+            return self.extract_into(&mut DirectorySink::new(&options.output), options, None, &completed);
+        }
+
+        if options.atomic != AtomicMode::Staged {
+            let mut sink = DirectorySink::new(&options.output).with_atomic_files(options.atomic == AtomicMode::PerFile);
+            let header = &self.entries.header;
+            let (mut journal, completed) = match options.resume {
+                true => Journal::resume(&options.output, header)?,
+                false => (Journal::create(&options.output, header), HashSet::new()),
+            };
+
+            return match self.extract_into(&mut sink, options, Some(&mut journal), &completed) {
+                Ok(report) => {
+                    journal.remove()?;
+                    Ok(report)
+                }
+                Err(err) => {
+                    // The error of the extraction matters more than failing to clean up after it.
+                    let _ = journal.abandon();
+                    Err(err)
+                }
+            };
+        }
+
+        if options.resume {
+            let err = anyhow!("A staged extraction can not be resumed, it always starts over");
+            options.observer.error(None, &err);
+            return Err(err);
+        }
+
+        let staging = StagingDirectory::create(&options.output).inspect_err(|err| options.observer.error(None, err))?;
+        let report = self.extract_to_sink(&mut DirectorySink::new(staging.path()), options)?;
+
+        staging
+            .verify(&report.plan, options)
+            .and_then(|()| staging.commit())
+            .inspect_err(|err| options.observer.error(None, err))?;
+
+        Ok(report)
+    }
+
+    /// Extracts what is selected in `options` into `sink`, and finishes the sink afterwards.
+    /// The output path of `options` is ignored.
+    pub fn extract_to_sink<S: ExtractSink + ?Sized>(&mut self, sink: &mut S, options: &ExtractOptions) -> Result<ExtractReport> {
+        self.extract_into(sink, options, None, &HashSet::new())
+    }
+
+    /// Extracts into `sink` the files which are not `completed`, recording the ones it completes in `journal`.
+    fn extract_into<S: ExtractSink + ?Sized>(
+        &mut self,
+        sink: &mut S,
+        options: &ExtractOptions,
+        journal: Option<&mut Journal>,
+        completed: &HashSet<(usize, u32)>,
+    ) -> Result<ExtractReport> {
+        let observer = options.observer.as_ref();
+
+        let mut plan = self.plan(options).inspect_err(|err| observer.error(None, err))?;
+        let resumed = skip_completed(&mut plan, sink, completed)?;
+        observer.archive_opened(&ArchiveSummary::from_plan(&self.entries, &plan));
+
+        let mut report = run_plan(&mut self.reader, plan, sink, options, journal)?;
+        report.resumed = resumed.len();
+        if options.dry_run {
+            return Ok(report);
+        }
+
+        if let Some(format) = options.manifest {
+            let mut extracted = report.plan.clone();
+            extracted.files.splice(0..0, resumed);
+            self.write_manifest(sink, &extracted, format, options.raw)
+                .inspect_err(|err| observer.error(None, err))?;
+        }
+
+        sink.finish()?;
+        Ok(report)
+    }
+
+    /// Writes the manifest describing the files of `plan` into the root of `sink`, with the layout of the archive if `raw`.
+    fn write_manifest<S: ExtractSink + ?Sized>(&mut self, sink: &mut S, plan: &ExtractPlan, format: ManifestFormat, raw: bool) -> Result<()> {
+        let mut manifest = Manifest::from_plan(&self.entries, plan);
+        if raw {
+            manifest.layout = Some(self.layout()?);
+        }
+
+        let manifest = manifest.to_string(format)?;
+        sink.write_file(Path::new(format.file_name()), manifest.len() as u64, &mut manifest.as_bytes())
+            .context("Failed to write the manifest")?;
+        Ok(())
+    }
+}
+
+/// This function visits all the files and folders from the specified folder.
+/// It then adds the files and folders it finds on the way as children to the parent
+fn visit_folder<T: Read + Seek + BufRead>(
+    reader: &mut T,
+    folder: Arc<Mutex<FolderNode>>,
+    entries: &SgaEntries,
+) -> Result<()> {
+    let files = FolderNode::read_files_from_folder(folder.clone(), reader, entries)?;
+    let subfolders = FolderNode::read_folders_from_folder(folder.clone(), reader, entries)?;
+
+    for file in files {
+        let file = Arc::new(file);
+        folder.lock().unwrap().add_child(Node::File(file));
+    }
+
+    for subfolder in subfolders {
+        let subfolder = Arc::new(Mutex::new(subfolder));
+        folder.lock().unwrap().add_child(Node::Folder(subfolder.clone()));
+
+        visit_folder(reader, subfolder, entries)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,384 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    io::{Seek, Write},
+};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    codec::CodecRegistry,
+    entires::{FileStorageType, FileVerificationType, SgaFileEntry, SgaFolderEntry, SgaHeader, SgaToC},
+    layout::ArchiveLayout,
+    pack::{block_hashes, ArchiveWriter},
+    utils::to_hex,
+};
+
+/// The data of a file as it's stored in an archive, with how it's stored.
+#[derive(Debug, Clone)]
+pub struct StoredFile {
+    /// The stored data.
+    pub data: Vec<u8>,
+
+    /// How the data is stored.
+    pub storage_type: FileStorageType,
+
+    /// Size of the file once decoded, in bytes.
+    pub uncompressed_size: u32,
+
+    /// How the file is verified when it's loaded.
+    pub verification_type: FileVerificationType,
+}
+
+impl StoredFile {
+    /// Encodes `data` as `storage_type` with `codecs`, at the codec specific compression `level` or each codec's default if `None`.
+    pub fn encode(
+        data: &[u8],
+        storage_type: FileStorageType,
+        verification_type: FileVerificationType,
+        codecs: &CodecRegistry,
+        level: Option<u32>,
+    ) -> Result<Self> {
+        Ok(Self {
+            data: codecs.encode(storage_type, data, level)?,
+            storage_type,
+            uncompressed_size: u32::try_from(data.len()).context("The file is too large to be stored")?,
+            verification_type,
+        })
+    }
+
+    /// The placed file for this data once it's at `data_offset` in the data blob of an archive with `block_size`.
+    pub fn placed(&self, data_offset: u64, block_size: u32) -> Result<PlacedFile> {
+        Ok(PlacedFile {
+            entry: SgaFileEntry {
+                name_offset: 0,
+                hash_offset: 0,
+                data_offset,
+                compressed_length: u32::try_from(self.data.len()).context("The file is too large to be stored")?,
+                uncompressed_size: self.uncompressed_size,
+                verification_type: self.verification_type,
+                storage_type: self.storage_type,
+                crc: crc32fast::hash(&self.data),
+            },
+            hashes: block_hashes(self.verification_type, &self.data, block_size),
+        })
+    }
+}
+
+/// The file entry of a file whose data is already in the data blob, with its block hashes.
+/// `ArchiveBuilder::build` sets the name and hash offsets of the entry.
+#[derive(Debug, Clone)]
+pub struct PlacedFile {
+    /// The file entry.
+    pub entry: SgaFileEntry,
+
+    /// The block hashes of the file, as they're written into the hash blob.
+    pub hashes: Vec<u8>,
+}
+
+/// A table of contents of an `ArchiveBuilder`, with its files keyed by their path.
+#[derive(Debug, Clone)]
+struct TocBuilder<F> {
+    alias: String,
+    name: String,
+    files: BTreeMap<String, F>,
+}
+
+/// Builds a new archive out of tables of contents holding files at paths, the folders are made from the paths.
+///
+/// Every file is given as a handle of type `F`, for example the index of a file entry in another archive,
+/// which `write` turns into the stored data of the file while writing it.
+/// Folders, files and strings are laid out in a fixed order, so the same files always give the same archive.
+#[derive(Debug, Clone)]
+pub struct ArchiveBuilder<F> {
+    /// Archive version.
+    pub version: u16,
+
+    /// Product id.
+    pub product: u16,
+
+    /// Name of the archive.
+    pub name: String,
+
+    /// Block size of the archive, which block hashes are computed over.
+    pub block_size: u32,
+
+    /// Unknown value written after the data blob length, see `SgaHeader::unknown`.
+    pub unknown: u32,
+
+    /// Whether files with identical stored data share it, so it's only stored once. On by default.
+    pub deduplicate: bool,
+
+    tocs: Vec<TocBuilder<F>>,
+}
+
+impl<F> ArchiveBuilder<F> {
+    /// Creates an empty version 10 archive named `name`.
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Self { version: 10, product: 0, name: name.into(), block_size: 4096, unknown: 1, deduplicate: true, tocs: Vec::new() }
+    }
+
+    /// Creates an empty archive with the version, product, name, block size and unknown value of `header`.
+    pub fn with_header(header: &SgaHeader) -> Self {
+        Self {
+            version: header.version,
+            product: header.product,
+            name: header.name.clone(),
+            block_size: header.block_size,
+            unknown: header.unknown,
+            deduplicate: true,
+            tocs: Vec::new(),
+        }
+    }
+
+    /// Adds an empty table of contents, unless there already is one with `alias`. Aliases are compared ignoring case.
+    pub fn add_toc<A: Into<String>, N: Into<String>>(&mut self, alias: A, name: N) {
+        let alias = alias.into();
+        if self.toc_position(&alias).is_none() {
+            self.tocs.push(TocBuilder { alias, name: name.into(), files: BTreeMap::new() });
+        }
+    }
+
+    /// Adds `file` at `path` inside the table of contents with `alias`, which is added if needed with the alias as its name.
+    /// Path components can be separated by `/` or `\`. Returns the file which was at the same path before.
+    pub fn add_file<S: AsRef<str>>(&mut self, alias: &str, path: S, file: F) -> Option<F> {
+        let position = match self.toc_position(alias) {
+            Some(position) => position,
+            None => {
+                self.add_toc(alias, alias);
+                self.tocs.len() - 1
+            }
+        };
+
+        self.tocs[position].files.insert(normalize_path(path.as_ref()), file)
+    }
+
+    /// Removes the file at `path` inside the table of contents with `alias`, and returns it.
+    pub fn remove_file<S: AsRef<str>>(&mut self, alias: &str, path: S) -> Option<F> {
+        let position = self.toc_position(alias)?;
+        self.tocs[position].files.remove(&normalize_path(path.as_ref()))
+    }
+
+    /// Returns the file at `path` inside the table of contents with `alias`.
+    pub fn file<S: AsRef<str>>(&self, alias: &str, path: S) -> Option<&F> {
+        let toc = &self.tocs[self.toc_position(alias)?];
+        toc.files.get(&normalize_path(path.as_ref()))
+    }
+
+    /// Every file with the alias of its table of contents and its path separated by `/`, in path order.
+    pub fn files(&self) -> impl Iterator<Item = (&str, &str, &F)> {
+        self.tocs
+            .iter()
+            .flat_map(|toc| toc.files.iter().map(|(path, file)| (toc.alias.as_str(), path.as_str(), file)))
+    }
+
+    /// Every file in the order `write` and `build` ask for them, which is the order of their entries in the archive.
+    pub fn files_in_layout_order(&self) -> Vec<&F> {
+        let mut files = Vec::with_capacity(self.file_count());
+        for toc in &self.tocs {
+            for folder in folder_tree(toc) {
+                files.extend(folder.files.iter().map(|(_, path)| &toc.files[*path]));
+            }
+        }
+        files
+    }
+
+    /// Number of files in every table of contents.
+    pub fn file_count(&self) -> usize {
+        self.tocs.iter().map(|toc| toc.files.len()).sum()
+    }
+
+    /// Number of tables of contents.
+    pub fn toc_count(&self) -> usize {
+        self.tocs.len()
+    }
+
+    /// Writes the archive into `writer`, asking `stored_data` for the stored data of each file in the order they're written.
+    /// Returns the layout of the written archive.
+    pub fn write<W, D>(&self, writer: W, mut stored_data: D) -> Result<ArchiveLayout>
+    where
+        W: Write + Seek,
+        D: FnMut(&F) -> Result<StoredFile>,
+    {
+        let mut writer = ArchiveWriter::new(writer)?.deduplicate(self.deduplicate);
+        let layout = self.build(|file| {
+            let stored = stored_data(file)?;
+            let placed = stored.placed(0, self.block_size)?;
+            let data_offset = writer.add_data(&stored.data)?;
+            Ok(PlacedFile { entry: SgaFileEntry { data_offset, ..placed.entry }, hashes: placed.hashes })
+        })?;
+        let (_, layout) = writer.finish(layout)?;
+
+        Ok(layout)
+    }
+
+    /// Lays out the tables of contents, folders, files and strings of the archive, asking `place` for the file entry of
+    /// each file in the order they're laid out. The data of the files must already be somewhere in the data blob.
+    ///
+    /// Returns the layout without placing its header blob, so the offsets and lengths of the header are left at zero.
+    pub fn build<D>(&self, mut place: D) -> Result<ArchiveLayout>
+    where
+        D: FnMut(&F) -> Result<PlacedFile>,
+    {
+        let mut strings = StringBlob::default();
+        let mut layout = ArchiveLayout {
+            header: SgaHeader {
+                magic: *b"_ARCHIVE",
+                version: self.version,
+                product: self.product,
+                name: self.name.clone(),
+                header_blob_offset: 0,
+                header_blob_length: 0,
+                data_offset: 0,
+                data_blob_length: 0,
+                unknown: self.unknown,
+                toc_data_offset: 0,
+                toc_data_count: 0,
+                folder_data_offset: 0,
+                folder_data_count: 0,
+                file_data_offset: 0,
+                file_data_count: 0,
+                string_offset: 0,
+                string_length: 0,
+                block_size: self.block_size,
+                signature: [0; 256],
+                file_hash_offset: 0,
+                file_hash_length: 0,
+            },
+            tocs: Vec::with_capacity(self.tocs.len()),
+            folders: Vec::new(),
+            files: Vec::with_capacity(self.file_count()),
+            strings: Vec::new(),
+            file_hashes: String::new(),
+        };
+
+        let mut hashes = Vec::new();
+
+        for toc in &self.tocs {
+            let folders = folder_tree(toc);
+            let first_folder = layout.folders.len() as u32;
+            let first_file = layout.files.len() as u32;
+
+            for folder in &folders {
+                let file_start_index = layout.files.len() as u32;
+                for (name, path) in &folder.files {
+                    let placed = place(&toc.files[*path]).with_context(|| format!("Failed to read '{}/{}'", toc.alias, path))?;
+                    layout.files.push(SgaFileEntry {
+                        name_offset: strings.add(name),
+                        hash_offset: hashes.len() as u32,
+                        ..placed.entry
+                    });
+                    hashes.extend(placed.hashes);
+                }
+
+                let folder_start_index = folder.children.start + first_folder;
+                layout.folders.push(SgaFolderEntry {
+                    name_offset: strings.add(&folder.path),
+                    folder_start_index,
+                    folder_end_index: folder_start_index + folder.children.len() as u32,
+                    file_start_index,
+                    file_end_index: layout.files.len() as u32,
+                });
+            }
+
+            layout.tocs.push(SgaToC {
+                alias: toc.alias.clone(),
+                name: toc.name.clone(),
+                folder_start_index: first_folder,
+                folder_end_index: layout.folders.len() as u32,
+                file_start_index: first_file,
+                file_end_index: layout.files.len() as u32,
+                folder_root_index: first_folder,
+            });
+        }
+
+        layout.strings = strings.strings;
+        layout.file_hashes = to_hex(&hashes);
+
+        Ok(layout)
+    }
+
+    fn toc_position(&self, alias: &str) -> Option<usize> {
+        self.tocs.iter().position(|toc| toc.alias.eq_ignore_ascii_case(alias))
+    }
+}
+
+/// A folder of a table of contents, as it's written.
+struct FolderLayout<'a> {
+    /// Path of the folder separated by `\`, empty for the root folder.
+    path: String,
+
+    /// Positions of the subfolders among the folders of the table of contents.
+    children: std::ops::Range<u32>,
+
+    /// Name and path of every file directly inside the folder.
+    files: Vec<(&'a str, &'a str)>,
+}
+
+/// Lays out the folders of `toc` breadth first, so the subfolders of each folder are next to each other.
+fn folder_tree<F>(toc: &TocBuilder<F>) -> Vec<FolderLayout<'_>> {
+    let mut subfolders: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut files: HashMap<String, Vec<(&str, &str)>> = HashMap::new();
+    subfolders.entry(String::new()).or_default();
+
+    for path in toc.files.keys() {
+        let (folder, name) = path.rsplit_once('/').unwrap_or(("", path));
+        files.entry(folder.to_string()).or_default().push((name, path));
+
+        let mut child = folder.to_string();
+        while !child.is_empty() {
+            let parent = child.rsplit_once('/').map_or("", |(parent, _)| parent).to_string();
+            let siblings = subfolders.entry(parent.clone()).or_default();
+            if !siblings.contains(&child) {
+                siblings.push(child.clone());
+            }
+            subfolders.entry(child).or_default();
+            child = parent;
+        }
+    }
+
+    let mut folders = Vec::new();
+    let mut queue = VecDeque::from([String::new()]);
+    let mut next = 1;
+    while let Some(folder) = queue.pop_front() {
+        let mut children = subfolders.remove(&folder).unwrap_or_default();
+        children.sort();
+
+        let start = next;
+        next += children.len() as u32;
+        queue.extend(children);
+
+        let mut folder_files = files.remove(&folder).unwrap_or_default();
+        folder_files.sort();
+        folders.push(FolderLayout { path: folder.replace('/', "\\"), children: start..next, files: folder_files });
+    }
+
+    folders
+}
+
+/// The strings of an archive, each one stored once.
+#[derive(Debug, Default)]
+struct StringBlob {
+    strings: Vec<String>,
+    offsets: HashMap<String, u32>,
+    length: u32,
+}
+
+impl StringBlob {
+    /// Adds `string` if it isn't there yet, and returns its offset.
+    fn add(&mut self, string: &str) -> u32 {
+        if let Some(&offset) = self.offsets.get(string) {
+            return offset;
+        }
+
+        let offset = self.length;
+        self.length += string.len() as u32 + 1;
+        self.strings.push(string.to_string());
+        self.offsets.insert(string.to_string(), offset);
+        offset
+    }
+}
+
+/// Separates the components of `path` with `/`, without leading or trailing separators.
+pub(crate) fn normalize_path(path: &str) -> String {
+    path.split(['/', '\\']).filter(|component| !component.is_empty()).collect::<Vec<_>>().join("/")
+}
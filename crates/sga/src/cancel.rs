@@ -0,0 +1,85 @@
+use std::{
+    io::{self, Read},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use thiserror::Error;
+
+/// Returned when an extraction stops because its `CancellationToken` was cancelled.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("Extraction was cancelled")]
+pub struct Cancelled;
+
+impl Cancelled {
+    /// Returns true if `err` was caused by a cancellation.
+    pub fn is_cause_of(err: &anyhow::Error) -> bool {
+        err.chain().any(|cause| {
+            cause.is::<Cancelled>()
+                || cause
+                    .downcast_ref::<io::Error>()
+                    .and_then(|err| err.get_ref())
+                    .is_some_and(|inner| inner.is::<Cancelled>())
+        })
+    }
+}
+
+/// A cheaply clonable flag used to stop an extraction from another thread.
+///
+/// All clones share the same state, so cancelling one cancels them all.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Creates a token which is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests the extraction using this token to stop.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns true once `cancel` has been called on this token or any of its clones.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Returns `Err(Cancelled)` if the token has been cancelled.
+    pub fn check(&self) -> Result<(), Cancelled> {
+        match self.is_cancelled() {
+            true => Err(Cancelled),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Wraps a reader and fails with a `Cancelled` error on the next read once `token` is cancelled.
+/// This makes streaming copies stop between chunks.
+pub struct CancellableReader<'a, R> {
+    inner: R,
+    token: Option<&'a CancellationToken>,
+}
+
+impl<'a, R: Read> CancellableReader<'a, R> {
+    /// Wraps `inner`, without a token the reader never cancels.
+    pub fn new(inner: R, token: Option<&'a CancellationToken>) -> Self {
+        Self { inner, token }
+    }
+}
+
+impl<R: Read> Read for CancellableReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(token) = self.token {
+            // `Interrupted` would make `io::copy` retry forever, so use `Other` instead
+            token.check().map_err(io::Error::other)?;
+        }
+
+        self.inner.read(buf)
+    }
+}
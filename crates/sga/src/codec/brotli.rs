@@ -0,0 +1,29 @@
+use std::io::{Read, Write};
+
+use brotli::{CompressorWriter, Decompressor};
+
+use super::{Codec, CodecError};
+
+/// The default brotli quality used when encoding, ranges from 0 to 11.
+const DEFAULT_BROTLI_QUALITY: u32 = 11;
+
+/// The default brotli window size used when encoding.
+const DEFAULT_BROTLI_WINDOW: u32 = 22;
+
+/// Codec for brotli compressed files, used by both `StreamCompressBrotli` and `BufferCompressBrotli`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BrotliCodec;
+
+impl Codec for BrotliCodec {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>, _uncompressed_length: usize) -> Result<Box<dyn Read + 'a>, CodecError> {
+        Ok(Box::new(Decompressor::new(input, 4096))) // 4KB buffer
+    }
+
+    fn encode(&self, data: &[u8], level: Option<u32>) -> Result<Vec<u8>, CodecError> {
+        let quality = level.unwrap_or(DEFAULT_BROTLI_QUALITY).min(11);
+        let mut encoder = CompressorWriter::new(Vec::new(), 4096, quality, DEFAULT_BROTLI_WINDOW);
+        encoder.write_all(data)?;
+        encoder.flush()?;
+        Ok(encoder.into_inner())
+    }
+}
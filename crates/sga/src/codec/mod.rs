@@ -0,0 +1,178 @@
+mod store;
+pub use store::*;
+
+mod zlib;
+pub use zlib::*;
+
+mod brotli;
+pub use brotli::*;
+
+use std::{collections::HashMap, fmt, io::{self, Read}, sync::Arc};
+
+use thiserror::Error;
+
+use crate::entires::FileStorageType;
+
+#[derive(Error, Debug)]
+pub enum CodecError {
+    #[error("No codec is registered for storage type `{0}`")]
+    UnsupportedStorageType(u8),
+    #[error("The codec for storage type `{0}` can not encode data")]
+    EncodingNotSupported(u8),
+    #[error("Decoded data has the wrong length: {0}")]
+    LengthMismatch(String),
+    #[error("Invalid zlib header: {0}")]
+    InvalidZlibHeader(String),
+    #[error("zlib stream requires preset dictionary `{0:#010x}`, which is not supported")]
+    PresetDictionary(u32),
+    #[error("Checksum mismatch: {0}")]
+    ChecksumMismatch(String),
+    #[error("Compressed data is corrupt: {0}")]
+    CorruptData(String),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// Decodes (and optionally encodes) the stored bytes of files with a given `FileStorageType`.
+pub trait Codec: Send + Sync {
+    /// Wraps `input`, which yields exactly the bytes stored in the archive, in a reader producing the decoded data.
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>, uncompressed_length: usize) -> Result<Box<dyn Read + 'a>, CodecError>;
+
+    /// Encodes `data` so it can be stored in an archive.
+    /// `level` is a codec specific compression level, `None` picks the codec's default.
+    fn encode(&self, _data: &[u8], _level: Option<u32>) -> Result<Vec<u8>, CodecError> {
+        Err(CodecError::EncodingNotSupported(u8::MAX))
+    }
+}
+
+/// Maps storage types to the codecs used to read and write them.
+///
+/// `CodecRegistry::default()` contains the codecs for every storage type known to this crate,
+/// additional ones can be added with `register`.
+#[derive(Clone)]
+pub struct CodecRegistry {
+    codecs: HashMap<u8, Arc<dyn Codec>>,
+}
+
+impl CodecRegistry {
+    /// Creates a registry without any codecs.
+    pub fn empty() -> Self {
+        Self { codecs: HashMap::new() }
+    }
+
+    /// Registers `codec` for `storage_type`, returning the codec it replaced.
+    pub fn register<C: Codec + 'static>(&mut self, storage_type: FileStorageType, codec: C) -> Option<Arc<dyn Codec>> {
+        self.codecs.insert(storage_type.to_u8(), Arc::new(codec))
+    }
+
+    /// Removes the codec for `storage_type`, returning it.
+    pub fn unregister(&mut self, storage_type: FileStorageType) -> Option<Arc<dyn Codec>> {
+        self.codecs.remove(&storage_type.to_u8())
+    }
+
+    /// Returns the codec registered for `storage_type`.
+    pub fn get(&self, storage_type: FileStorageType) -> Option<&dyn Codec> {
+        self.codecs.get(&storage_type.to_u8()).map(|codec| codec.as_ref())
+    }
+
+    /// Returns true if a codec is registered for `storage_type`.
+    pub fn contains(&self, storage_type: FileStorageType) -> bool {
+        self.codecs.contains_key(&storage_type.to_u8())
+    }
+
+    /// Decodes `data` stored as `storage_type`, checking that it decodes to `uncompressed_length` bytes.
+    pub fn decode(&self, storage_type: FileStorageType, data: &[u8], uncompressed_length: usize) -> Result<Vec<u8>, CodecError> {
+        let codec = self.get(storage_type).ok_or(CodecError::UnsupportedStorageType(storage_type.to_u8()))?;
+        let mut decoder = codec.decoder(Box::new(data), uncompressed_length)?;
+
+        let mut decoded = Vec::with_capacity(uncompressed_length);
+        decoder.read_to_end(&mut decoded)?;
+        check_length(decoded.len(), uncompressed_length)?;
+
+        Ok(decoded)
+    }
+
+    /// Encodes `data` to be stored as `storage_type`.
+    pub fn encode(&self, storage_type: FileStorageType, data: &[u8], level: Option<u32>) -> Result<Vec<u8>, CodecError> {
+        let codec = self.get(storage_type).ok_or(CodecError::UnsupportedStorageType(storage_type.to_u8()))?;
+
+        codec.encode(data, level).map_err(|err| match err {
+            CodecError::EncodingNotSupported(_) => CodecError::EncodingNotSupported(storage_type.to_u8()),
+            err => err,
+        })
+    }
+}
+
+impl Default for CodecRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(FileStorageType::Store, StoreCodec);
+        registry.register(FileStorageType::StreamCompress, ZlibCodec);
+        registry.register(FileStorageType::BufferCompress, ZlibCodec);
+        registry.register(FileStorageType::StreamCompressBrotli, BrotliCodec);
+        registry.register(FileStorageType::BufferCompressBrotli, BrotliCodec);
+        registry
+    }
+}
+
+impl fmt::Debug for CodecRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut storage_types: Vec<_> = self.codecs.keys().collect();
+        storage_types.sort();
+        f.debug_struct("CodecRegistry").field("storage_types", &storage_types).finish()
+    }
+}
+
+/// Wraps a decoder and fails with a `LengthMismatch` error if it yields more or fewer bytes than expected.
+pub struct ExactLengthReader<R> {
+    inner: R,
+    expected: u64,
+    remaining: u64,
+}
+
+impl<R: Read> ExactLengthReader<R> {
+    /// Creates a reader which must yield exactly `expected` bytes from `inner`.
+    pub fn new(inner: R, expected: u64) -> Self {
+        Self { inner, expected, remaining: expected }
+    }
+
+    fn mismatch(&self, actual: u64) -> io::Error {
+        let err = CodecError::LengthMismatch(format!("expected {} bytes, got {}", self.expected, actual));
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+impl<R: Read> Read for ExactLengthReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.remaining == 0 {
+            // Make sure the decoder really is finished, it may still need to validate a trailer
+            let mut extra = [0u8; 1];
+            return match self.inner.read(&mut extra)? {
+                0 => Ok(0),
+                _ => Err(self.mismatch(self.expected + 1)),
+            };
+        }
+
+        let limit = buf.len().min(self.remaining.min(usize::MAX as u64) as usize);
+        let read = self.inner.read(&mut buf[..limit])?;
+        if read == 0 {
+            return Err(self.mismatch(self.expected - self.remaining));
+        }
+
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+/// Returns an error if `actual` decoded bytes do not match the `expected` length from the file entry.
+pub(crate) fn check_length(actual: usize, expected: usize) -> Result<(), CodecError> {
+    if actual != expected {
+        return Err(CodecError::LengthMismatch(format!("expected {} bytes, got {}", expected, actual)));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,17 @@
+use std::io::Read;
+
+use super::{Codec, CodecError};
+
+/// Codec for files stored without compression.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StoreCodec;
+
+impl Codec for StoreCodec {
+    fn decoder<'a>(&self, input: Box<dyn Read + 'a>, _uncompressed_length: usize) -> Result<Box<dyn Read + 'a>, CodecError> {
+        Ok(input)
+    }
+
+    fn encode(&self, data: &[u8], _level: Option<u32>) -> Result<Vec<u8>, CodecError> {
+        Ok(data.to_vec())
+    }
+}
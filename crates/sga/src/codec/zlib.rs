@@ -0,0 +1,255 @@
+use std::io::{self, BufReader, ErrorKind, Read, Write};
+
+use flate2::{bufread::DeflateDecoder, write::ZlibEncoder, Compression};
+
+use super::{Codec, CodecError};
+
+/// Compression method 8 is the only one defined by RFC 1950, deflate.
+const ZLIB_METHOD_DEFLATE: u8 = 8;
+
+/// The largest window size allowed by RFC 1950, as log2(window size) - 8.
+const ZLIB_MAX_WINDOW_BITS: u8 = 7;
+
+/// Flag in the FLG byte signalling a preset dictionary id follows the header.
+const ZLIB_FLAG_PRESET_DICTIONARY: u8 = 0x20;
+
+/// The largest prime smaller than 65536, used as the Adler-32 modulus.
+const ADLER_MODULUS: u32 = 65521;
+
+/// The largest number of bytes which can be summed before the Adler-32 sums overflow a u32.
+const ADLER_CHUNK_SIZE: usize = 5552;
+
+/// Codec for zlib (RFC 1950) compressed files.
+///
+/// `StreamCompress` and `BufferCompress` files are stored with the same layout, a two byte zlib header,
+/// a raw deflate stream, and a big endian Adler-32 checksum of the uncompressed data.
+/// The header and the checksum are both validated while decoding.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ZlibCodec;
+
+impl Codec for ZlibCodec {
+    fn decoder<'a>(&self, mut input: Box<dyn Read + 'a>, _uncompressed_length: usize) -> Result<Box<dyn Read + 'a>, CodecError> {
+        ZlibHeader::parse(&mut input)?;
+
+        Ok(Box::new(ZlibDecoder {
+            deflate: DeflateDecoder::new(BufReader::new(input)),
+            adler: Adler32::new(),
+            finished: false,
+        }))
+    }
+
+    fn encode(&self, data: &[u8], level: Option<u32>) -> Result<Vec<u8>, CodecError> {
+        let level = level.map(|level| Compression::new(level.min(9))).unwrap_or_default();
+        let mut encoder = ZlibEncoder::new(Vec::new(), level);
+        encoder.write_all(data)?;
+        Ok(encoder.finish()?)
+    }
+}
+
+/// The two byte header at the start of a zlib stream.
+#[derive(Debug, Clone, Copy)]
+pub struct ZlibHeader {
+    /// Size of the LZ77 window in bytes.
+    pub window_size: u32,
+
+    /// Compression level hint, from 0 (fastest) to 3 (maximum compression).
+    pub level: u8,
+}
+
+impl ZlibHeader {
+    /// Parses and validates a zlib header, leaving the reader at the start of the deflate stream.
+    pub fn parse<R: Read>(reader: &mut R) -> Result<Self, CodecError> {
+        let mut header = [0u8; 2];
+        reader.read_exact(&mut header).map_err(|err| match err.kind() {
+            ErrorKind::UnexpectedEof => CodecError::InvalidZlibHeader("data is too short to hold a zlib header".to_string()),
+            _ => CodecError::Io(err),
+        })?;
+        let [cmf, flg] = header;
+
+        if (u16::from(cmf) << 8 | u16::from(flg)) % 31 != 0 {
+            return Err(CodecError::InvalidZlibHeader(format!("header check bits do not match, found {:#04x} {:#04x}", cmf, flg)));
+        }
+
+        let method = cmf & 0x0F;
+        if method != ZLIB_METHOD_DEFLATE {
+            return Err(CodecError::InvalidZlibHeader(format!("compression method {} is not deflate", method)));
+        }
+
+        let window_bits = cmf >> 4;
+        if window_bits > ZLIB_MAX_WINDOW_BITS {
+            return Err(CodecError::InvalidZlibHeader(format!("window size of 2^{} bytes is larger than 32KB", window_bits + 8)));
+        }
+
+        if flg & ZLIB_FLAG_PRESET_DICTIONARY != 0 {
+            let mut dictionary_id = [0u8; 4];
+            reader.read_exact(&mut dictionary_id)?;
+            return Err(CodecError::PresetDictionary(u32::from_be_bytes(dictionary_id)));
+        }
+
+        Ok(Self {
+            window_size: 1 << (window_bits + 8),
+            level: flg >> 6,
+        })
+    }
+}
+
+/// Decodes the deflate stream of a zlib stream, and checks the Adler-32 trailer once it ends.
+struct ZlibDecoder<'a> {
+    deflate: DeflateDecoder<BufReader<Box<dyn Read + 'a>>>,
+    adler: Adler32,
+    finished: bool,
+}
+
+impl ZlibDecoder<'_> {
+    /// Reads the Adler-32 trailer following the deflate stream and compares it to the decoded data.
+    fn check_trailer(&mut self) -> io::Result<()> {
+        let mut trailer = [0u8; 4];
+        self.deflate.get_mut().read_exact(&mut trailer).map_err(|err| match err.kind() {
+            ErrorKind::UnexpectedEof => invalid_data(CodecError::ChecksumMismatch("zlib stream is missing its Adler-32 trailer".to_string())),
+            _ => err,
+        })?;
+
+        let expected = u32::from_be_bytes(trailer);
+        let actual = self.adler.finish();
+        if expected != actual {
+            return Err(invalid_data(CodecError::ChecksumMismatch(format!(
+                "Adler-32 of decoded data is {:#010x}, expected {:#010x}",
+                actual, expected
+            ))));
+        }
+
+        Ok(())
+    }
+}
+
+impl Read for ZlibDecoder<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.finished {
+            return Ok(0);
+        }
+
+        let read = self.deflate.read(buf).map_err(|err| match err.kind() {
+            ErrorKind::InvalidInput | ErrorKind::InvalidData => invalid_data(CodecError::CorruptData(err.to_string())),
+            _ => err,
+        })?;
+
+        if read == 0 && !buf.is_empty() {
+            self.finished = true;
+            self.check_trailer()?;
+        }
+
+        self.adler.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// Wraps a codec error in an io error, so it can be returned from a reader.
+fn invalid_data(err: CodecError) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, err)
+}
+
+/// Running Adler-32 checksum, as defined by RFC 1950.
+#[derive(Debug, Clone, Copy)]
+pub struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Adler32 {
+    /// Creates the checksum of zero bytes.
+    pub fn new() -> Self {
+        Self { a: 1, b: 0 }
+    }
+
+    /// Adds `data` to the checksum.
+    pub fn update(&mut self, data: &[u8]) {
+        for chunk in data.chunks(ADLER_CHUNK_SIZE) {
+            for &byte in chunk {
+                self.a += u32::from(byte);
+                self.b += self.a;
+            }
+            self.a %= ADLER_MODULUS;
+            self.b %= ADLER_MODULUS;
+        }
+    }
+
+    /// Returns the checksum of all the data seen so far.
+    pub fn finish(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+}
+
+impl Default for Adler32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use super::*;
+
+    const DATA: &[u8] = b"zlib zlib zlib zlib zlib zlib zlib zlib zlib zlib zlib zlib";
+
+    /// Decodes `stored` with `ZlibCodec`, returning the error of the header or of the stream.
+    fn decode(stored: &[u8]) -> Result<Vec<u8>, CodecError> {
+        let mut decoder = ZlibCodec.decoder(Box::new(Cursor::new(stored.to_vec())), DATA.len())?;
+        let mut decoded = Vec::new();
+        decoder.read_to_end(&mut decoded).map_err(|err| match err.into_inner().map(|err| err.downcast::<CodecError>()) {
+            Some(Ok(err)) => *err,
+            Some(Err(err)) => CodecError::Io(io::Error::other(err)),
+            None => CodecError::Io(ErrorKind::Other.into()),
+        })?;
+        Ok(decoded)
+    }
+
+    fn encoded() -> Vec<u8> {
+        ZlibCodec.encode(DATA, None).unwrap()
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        assert_eq!(decode(&encoded()).unwrap(), DATA);
+    }
+
+    #[test]
+    fn rejects_a_bad_header_check() {
+        let mut stored = encoded();
+        stored[1] ^= 0x01;
+        assert!(matches!(decode(&stored), Err(CodecError::InvalidZlibHeader(_))));
+    }
+
+    #[test]
+    fn rejects_a_preset_dictionary() {
+        let cmf = 0x78;
+        let flg = (0..32)
+            .map(|check| ZLIB_FLAG_PRESET_DICTIONARY | check)
+            .find(|flg| (u16::from(cmf) << 8 | u16::from(*flg)) % 31 == 0)
+            .unwrap();
+        let mut stored = vec![cmf, flg, 0x12, 0x34, 0x56, 0x78];
+        stored.extend_from_slice(&encoded()[2..]);
+        assert!(matches!(decode(&stored), Err(CodecError::PresetDictionary(0x12345678))));
+    }
+
+    #[test]
+    fn rejects_a_corrupted_trailer() {
+        let mut stored = encoded();
+        *stored.last_mut().unwrap() ^= 0xFF;
+        assert!(matches!(decode(&stored), Err(CodecError::ChecksumMismatch(_))));
+    }
+
+    #[test]
+    fn rejects_a_missing_trailer() {
+        let stored = encoded();
+        assert!(matches!(decode(&stored[..stored.len() - 4]), Err(CodecError::ChecksumMismatch(_))));
+    }
+
+    #[test]
+    fn rejects_a_truncated_stream() {
+        let stored = encoded();
+        assert!(decode(&stored[..stored.len() / 2]).is_err());
+        assert!(matches!(decode(&stored[..1]), Err(CodecError::InvalidZlibHeader(_))));
+    }
+}
@@ -0,0 +1,144 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+
+use crate::{options::OverwritePolicy, plan::ExtractPlan, sink::{to_archive_path, ExtractSink}};
+
+/// Why a file could not simply be written to its path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConflictKind {
+    /// A file already exists at the path in the output.
+    Existing,
+
+    /// An earlier file of the extraction has the same path, ignoring case.
+    Collision {
+        /// Path of the earlier file.
+        with: PathBuf,
+    },
+}
+
+/// What was done about a conflict.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConflictResolution {
+    /// The file is written over the other one.
+    Overwritten,
+
+    /// The file is not extracted.
+    Skipped,
+
+    /// The file is written to another path.
+    Renamed(PathBuf),
+}
+
+/// A file whose path conflicts with an existing file or with another file of the extraction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// Path the file was planned to be written to, relative to the root of the output.
+    pub path: PathBuf,
+
+    /// What the file conflicts with.
+    pub kind: ConflictKind,
+
+    /// What was done about it.
+    pub resolution: ConflictResolution,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_reason(f, &self.path, &self.kind)?;
+
+        match &self.resolution {
+            ConflictResolution::Overwritten => write!(f, ", overwritten"),
+            ConflictResolution::Skipped => write!(f, ", skipped"),
+            ConflictResolution::Renamed(path) => write!(f, ", renamed to '{}'", path.display()),
+        }
+    }
+}
+
+fn write_reason(f: &mut impl fmt::Write, path: &Path, kind: &ConflictKind) -> fmt::Result {
+    match kind {
+        ConflictKind::Existing => write!(f, "'{}' already exists", path.display()),
+        ConflictKind::Collision { with } => write!(f, "'{}' collides with '{}'", path.display(), with.display()),
+    }
+}
+
+/// Finds the planned files whose path collides with an earlier one ignoring case, or with a file already in `sink`,
+/// and applies `policy` to them. Nothing is written, with `OverwritePolicy::Error` every conflict is listed in the error.
+pub(crate) fn resolve_conflicts<S: ExtractSink + ?Sized>(plan: &mut ExtractPlan, sink: &S, policy: OverwritePolicy) -> Result<Vec<Conflict>> {
+    let mut conflicts = Vec::new();
+    let mut first_with_path: HashMap<String, PathBuf> = HashMap::new();
+    let mut taken: HashSet<String> = plan.files.iter().map(|planned| fold_path(&planned.path)).collect();
+    let mut skipped = HashSet::new();
+
+    for (index, planned) in plan.files.iter_mut().enumerate() {
+        let key = fold_path(&planned.path);
+        let kind = match first_with_path.get(&key) {
+            Some(with) => ConflictKind::Collision { with: with.clone() },
+            None => {
+                first_with_path.insert(key, planned.path.clone());
+                match sink.exists(&planned.path)? {
+                    true => ConflictKind::Existing,
+                    false => continue,
+                }
+            }
+        };
+
+        let path = planned.path.clone();
+        let resolution = match policy {
+            OverwritePolicy::Overwrite | OverwritePolicy::Error => ConflictResolution::Overwritten,
+            OverwritePolicy::Skip => {
+                skipped.insert(index);
+                ConflictResolution::Skipped
+            }
+            OverwritePolicy::Rename => {
+                let renamed = free_path(&planned.path, sink, &mut taken)?;
+                planned.path = renamed.clone();
+                ConflictResolution::Renamed(renamed)
+            }
+        };
+
+        conflicts.push(Conflict { path, kind, resolution });
+    }
+
+    if policy == OverwritePolicy::Error && !conflicts.is_empty() {
+        let mut list = String::new();
+        for conflict in &conflicts {
+            list.push_str("\n  ");
+            write_reason(&mut list, &conflict.path, &conflict.kind)?;
+        }
+        bail!("{} files conflict with other files:{}", conflicts.len(), list);
+    }
+
+    let mut index = 0;
+    plan.files.retain(|_| {
+        index += 1;
+        !skipped.contains(&(index - 1))
+    });
+
+    Ok(conflicts)
+}
+
+/// The path compared to find collisions, separators and case don't matter.
+fn fold_path(path: &Path) -> String {
+    to_archive_path(path).to_lowercase()
+}
+
+/// Finds a path next to `path` with a ` (n)` suffix, which is neither taken by the extraction nor exists in `sink`.
+fn free_path<S: ExtractSink + ?Sized>(path: &Path, sink: &S, taken: &mut HashSet<String>) -> Result<PathBuf> {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().map(|extension| format!(".{}", extension.to_string_lossy())).unwrap_or_default();
+
+    for n in 1.. {
+        let candidate = path.with_file_name(format!("{} ({}){}", stem, n, extension));
+        if !taken.contains(&fold_path(&candidate)) && !sink.exists(&candidate)? {
+            taken.insert(fold_path(&candidate));
+            return Ok(candidate);
+        }
+    }
+
+    unreachable!()
+}
@@ -0,0 +1,358 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{BufRead, Read, Seek, Write},
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    archive::SgaArchive,
+    builder::{ArchiveBuilder, StoredFile},
+    entires::{FileStorageType, FileVerificationType, SgaFileEntry},
+    manifest::ManifestToc,
+    options::ExtractOptions,
+    subset::copy_stored,
+};
+
+/// Alias of the table of contents of a delta archive holding its `DeltaManifest`.
+pub const DELTA_TOC: &str = "__delta__";
+
+/// Path of the `DeltaManifest` inside the `DELTA_TOC` table of contents.
+pub const DELTA_MANIFEST: &str = "delta.json";
+
+/// What a delta archive changes, stored as JSON inside it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeltaManifest {
+    /// Fingerprint of the archive the delta applies to.
+    pub base: u32,
+
+    /// Fingerprint of the archive applying the delta gives.
+    pub target: u32,
+
+    /// Version of the target archive.
+    pub version: u16,
+
+    /// Product of the target archive.
+    pub product: u16,
+
+    /// Name of the target archive.
+    pub name: String,
+
+    /// Block size of the target archive.
+    pub block_size: u32,
+
+    /// Unknown value of the header of the target archive, see `SgaHeader::unknown`.
+    #[serde(default = "crate::entires::always_one")]
+    pub unknown: u32,
+
+    /// Every table of contents of the target archive, in archive order.
+    pub tocs: Vec<ManifestToc>,
+
+    /// Every file which was added, changed or removed, in path order.
+    pub files: Vec<DeltaFile>,
+}
+
+/// A file a delta archive adds, changes or removes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeltaFile {
+    /// Alias of the table of contents holding the file.
+    pub toc: String,
+
+    /// Path of the file inside its table of contents, separated by `/`.
+    pub path: String,
+
+    /// What happens to the file.
+    #[serde(flatten)]
+    pub change: DeltaChange,
+}
+
+/// What a delta archive does to a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "change", rename_all = "lowercase")]
+pub enum DeltaChange {
+    /// The file is new, its data is in the delta archive.
+    Added,
+
+    /// The file replaces the file of the base archive with `base_crc`, its data is in the delta archive.
+    Changed { base_crc: u32 },
+
+    /// The file of the base archive with `base_crc` is removed.
+    Removed { base_crc: u32 },
+}
+
+/// What `create_delta` or `apply_delta` wrote.
+#[derive(Debug, Clone, Default)]
+pub struct DeltaReport {
+    /// Number of files added.
+    pub added: usize,
+
+    /// Number of files changed.
+    pub changed: usize,
+
+    /// Number of files removed.
+    pub removed: usize,
+
+    /// Number of files which stay the same.
+    pub unchanged: usize,
+
+    /// Size of the data blob of the written archive.
+    pub stored_bytes: u64,
+
+    /// Size of the stored data several files share, which is only stored once.
+    pub shared_bytes: u64,
+}
+
+/// A file of an archive, as `create_delta` and `apply_delta` compare it.
+#[derive(Debug, Clone)]
+struct ArchiveFile {
+    /// Alias of the table of contents holding the file.
+    toc: String,
+
+    /// Index of the file entry.
+    index: usize,
+
+    /// The file entry.
+    entry: SgaFileEntry,
+
+    /// CRC32 of the stored data, the verified CRC of the entry or computed from the data if it has none.
+    crc: u32,
+}
+
+/// The files of an archive keyed by the alias of their table of contents in lowercase and their path.
+type ArchiveFiles = BTreeMap<(String, String), ArchiveFile>;
+
+/// Writes a delta archive into `writer`, holding the files of `new` which `old` doesn't have or stores different data for.
+///
+/// Files are compared by the CRC of their stored data and `SgaFileEntry::same_storage`. The data is only read
+/// for files whose entry has no verified CRC, to compute it.
+/// The changed files are stored at their paths like in any other archive, and a `DeltaManifest` listing every change
+/// is stored at `DELTA_MANIFEST` inside an extra table of contents with the alias `DELTA_TOC`.
+pub fn create_delta<R, S, W>(old: &mut SgaArchive<R>, new: &mut SgaArchive<S>, writer: W) -> Result<DeltaReport>
+where
+    R: Read + BufRead + Seek,
+    S: Read + BufRead + Seek,
+    W: Write + Seek,
+{
+    if new.toc(DELTA_TOC).is_some() {
+        bail!("The new archive has a table of contents named '{}', which delta archives use for themselves", DELTA_TOC);
+    }
+
+    let old_files = archive_files(old)?;
+    let new_files = archive_files(new)?;
+    let header = &new.entries.header;
+
+    let mut builder = ArchiveBuilder::with_header(header);
+    let mut report = DeltaReport::default();
+    let mut files = Vec::new();
+
+    for (key, file) in &new_files {
+        let change = match old_files.get(key) {
+            Some(base) if base.crc == file.crc && base.entry.same_storage(&file.entry) => {
+                report.unchanged += 1;
+                continue;
+            }
+            Some(base) => {
+                report.changed += 1;
+                DeltaChange::Changed { base_crc: base.crc }
+            }
+            None => {
+                report.added += 1;
+                DeltaChange::Added
+            }
+        };
+
+        let toc_name = new.toc(&file.toc).map_or(file.toc.as_str(), |toc| toc.name.as_str());
+        builder.add_toc(&file.toc, toc_name);
+        builder.add_file(&file.toc, &key.1, Some(file.index));
+        files.push(DeltaFile { toc: file.toc.clone(), path: key.1.clone(), change });
+    }
+
+    for (key, file) in &old_files {
+        if !new_files.contains_key(key) {
+            report.removed += 1;
+            files.push(DeltaFile { toc: file.toc.clone(), path: key.1.clone(), change: DeltaChange::Removed { base_crc: file.crc } });
+        }
+    }
+    files.sort_by(|a, b| (a.toc.to_lowercase(), &a.path).cmp(&(b.toc.to_lowercase(), &b.path)));
+
+    let manifest = DeltaManifest {
+        base: fingerprint(old_files.iter().map(|((_, path), file)| (path.as_str(), file))),
+        target: fingerprint(new_files.iter().map(|((_, path), file)| (path.as_str(), file))),
+        version: header.version,
+        product: header.product,
+        name: header.name.clone(),
+        block_size: header.block_size,
+        unknown: header.unknown,
+        tocs: new.tocs().iter().map(|toc| ManifestToc { alias: toc.alias.clone(), name: toc.name.clone() }).collect(),
+        files,
+    };
+    let manifest = serde_json::to_vec_pretty(&manifest)?;
+
+    builder.add_toc(DELTA_TOC, "Delta");
+    builder.add_file(DELTA_TOC, DELTA_MANIFEST, None);
+    let layout = builder.write(writer, |file| match file {
+        Some(index) => copy_stored(new, *index),
+        None => Ok(StoredFile {
+            data: manifest.clone(),
+            storage_type: FileStorageType::Store,
+            uncompressed_size: manifest.len() as u32,
+            verification_type: FileVerificationType::CRC,
+        }),
+    })?;
+
+    report.stored_bytes = layout.header.data_blob_length;
+    report.shared_bytes = layout.shared_bytes();
+    Ok(report)
+}
+
+/// Reads the `DeltaManifest` of a delta archive written by `create_delta`.
+pub fn read_delta_manifest<R: Read + BufRead + Seek>(delta: &mut SgaArchive<R>) -> Result<DeltaManifest> {
+    let Some(file) = archive_files(delta)?.remove(&(DELTA_TOC.to_string(), DELTA_MANIFEST.to_string())) else {
+        bail!("The archive is not a delta archive, it has no '{}/{}'", DELTA_TOC, DELTA_MANIFEST);
+    };
+
+    let stored = delta.read_stored(file.index)?;
+    if file.entry.storage_type != FileStorageType::Store || file.entry.verified_crc() != Some(crc32fast::hash(&stored)) {
+        bail!("The manifest of the delta archive is corrupt");
+    }
+    serde_json::from_slice(&stored).context("Invalid delta manifest")
+}
+
+/// Applies the `delta` archive written by `create_delta` to `old`, and writes the resulting archive into `writer`.
+///
+/// Fails before writing anything if `old` isn't the archive the delta was made from, going by its fingerprint
+/// and the CRC of every changed and removed file. The stored data of every file is copied as is and checked against
+/// its CRC if its verification type is CRC, and the files of the result are checked against the fingerprint of the
+/// archive the delta was made for. The result holds the same files stored the same way as that archive,
+/// its folders and data are laid out the way `ArchiveBuilder` lays them out.
+pub fn apply_delta<R, S, W>(old: &mut SgaArchive<R>, delta: &mut SgaArchive<S>, writer: W) -> Result<DeltaReport>
+where
+    R: Read + BufRead + Seek,
+    S: Read + BufRead + Seek,
+    W: Write + Seek,
+{
+    let manifest = read_delta_manifest(delta)?;
+    let old_files = archive_files(old)?;
+    let delta_files = archive_files(delta)?;
+
+    if fingerprint(old_files.iter().map(|((_, path), file)| (path.as_str(), file))) != manifest.base {
+        bail!("The archive is not the one the delta was made from");
+    }
+
+    let mut builder = ArchiveBuilder::new(manifest.name.clone());
+    builder.version = manifest.version;
+    builder.product = manifest.product;
+    builder.block_size = manifest.block_size;
+    builder.unknown = manifest.unknown;
+    for toc in &manifest.tocs {
+        builder.add_toc(&toc.alias, &toc.name);
+    }
+    for ((_, path), file) in &old_files {
+        // Files of tables of contents the target doesn't have are all removed by the delta.
+        if manifest.tocs.iter().any(|other| other.alias.eq_ignore_ascii_case(&file.toc)) {
+            builder.add_file(&file.toc, path, Source::Base(file.index));
+        }
+    }
+
+    let mut report = DeltaReport::default();
+    for file in &manifest.files {
+        let key = (file.toc.to_lowercase(), file.path.clone());
+        match (file.change, old_files.get(&key)) {
+            (DeltaChange::Added, None) => report.added += 1,
+            (DeltaChange::Changed { base_crc }, Some(base)) if base.crc == base_crc => report.changed += 1,
+            (DeltaChange::Removed { base_crc }, Some(base)) if base.crc == base_crc => {
+                report.removed += 1;
+                builder.remove_file(&file.toc, &file.path);
+                continue;
+            }
+            _ => bail!("'{}/{}' is not what the delta expects, the archive is not the one the delta was made from", file.toc, file.path),
+        }
+
+        let Some(changed) = delta_files.get(&key) else {
+            bail!("The delta archive has no data for '{}/{}'", file.toc, file.path);
+        };
+        builder.remove_file(&file.toc, &file.path);
+        builder.add_file(&file.toc, &file.path, Source::Delta(changed.index));
+    }
+    report.unchanged = builder.file_count() - report.added - report.changed;
+
+    let base_files: HashMap<usize, &ArchiveFile> = old_files.values().map(|file| (file.index, file)).collect();
+    let delta_files: HashMap<usize, &ArchiveFile> = delta_files.values().map(|file| (file.index, file)).collect();
+    let target = fingerprint(builder.files().map(|(_, path, source)| match source {
+        Source::Base(index) => (path, base_files[index]),
+        Source::Delta(index) => (path, delta_files[index]),
+    }));
+    if target != manifest.target {
+        bail!("Applying the delta does not give the archive it was made for");
+    }
+
+    let layout = builder.write(writer, |source| match source {
+        Source::Base(index) => copy_checked(old, *index).context("The archive is corrupt"),
+        Source::Delta(index) => copy_checked(delta, *index).context("The delta archive is corrupt"),
+    })?;
+
+    report.stored_bytes = layout.header.data_blob_length;
+    report.shared_bytes = layout.shared_bytes();
+    Ok(report)
+}
+
+/// Where `apply_delta` takes the data of a file from.
+#[derive(Debug, Clone, Copy)]
+enum Source {
+    /// The file entry at this index of the base archive.
+    Base(usize),
+
+    /// The file entry at this index of the delta archive.
+    Delta(usize),
+}
+
+/// Every file of `archive`. The stored data of files without a verified CRC is read to compute the CRC of their data.
+fn archive_files<R: Read + BufRead + Seek>(archive: &mut SgaArchive<R>) -> Result<ArchiveFiles> {
+    let plan = archive.plan(&ExtractOptions::default())?;
+    let mut files = BTreeMap::new();
+    for planned in &plan.files {
+        let index = planned.file.index().with_context(|| format!("'{}' has no file entry", planned.archive_path))?;
+        let entry = archive.entries.files[index].clone();
+        let crc = match entry.verified_crc() {
+            Some(crc) => crc,
+            None => crc32fast::hash(&archive.read_stored(index)?),
+        };
+        let file = ArchiveFile { toc: planned.toc.clone(), index, entry, crc };
+        files.insert((planned.toc.to_lowercase(), planned.archive_path.clone()), file);
+    }
+    Ok(files)
+}
+
+/// Reads the file entry at `index` of `archive` like `copy_stored`, and checks its CRC if its verification type is CRC.
+fn copy_checked<R: Read + BufRead + Seek>(archive: &mut SgaArchive<R>, index: usize) -> Result<StoredFile> {
+    let stored = copy_stored(archive, index)?;
+    let entry = &archive.entries.files[index];
+    if entry.verification_type == FileVerificationType::CRC && crc32fast::hash(&stored.data) != entry.crc {
+        bail!("CRC mismatch in file entry {}", index);
+    }
+    Ok(stored)
+}
+
+/// A CRC32 over the alias of the table of contents, the path and the data the entry stores of every file given with its path.
+/// Aliases are compared ignoring case, and the order the files are given in doesn't matter.
+fn fingerprint<'a, I>(files: I) -> u32
+where
+    I: IntoIterator<Item = (&'a str, &'a ArchiveFile)>,
+{
+    let mut files: Vec<_> = files.into_iter().map(|(path, file)| (file.toc.to_lowercase(), path, file)).collect();
+    files.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+
+    let mut hasher = crc32fast::Hasher::new();
+    for (toc, path, file) in files {
+        let entry = &file.entry;
+        for part in [toc.as_bytes(), b"\0", path.as_bytes(), b"\0"] {
+            hasher.update(part);
+        }
+        hasher.update(&file.crc.to_le_bytes());
+        hasher.update(&entry.compressed_length.to_le_bytes());
+        hasher.update(&entry.uncompressed_size.to_le_bytes());
+        hasher.update(&[entry.storage_type.to_u8(), entry.verification_type.to_u8()]);
+    }
+    hasher.finalize()
+}
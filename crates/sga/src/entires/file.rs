@@ -1,6 +1,9 @@
-use std::io::{BufRead, Read};
+use std::io::{self, BufRead, Read, Write};
+
+use byteorder::{LittleEndian, WriteBytesExt};
 
 use sga_macros::read_field;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -16,7 +19,7 @@ pub enum SgaFileEntryParseError {
 }
 
 /// Describes how a file is verified when it's loaded.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum FileVerificationType {
     /// No verification.
     None,
@@ -60,7 +63,7 @@ impl FileVerificationType {
 }
 
 /// Describes how a file is stored within an SGA archive.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum FileStorageType {
     /// Stored plainly.
     Store,
@@ -94,6 +97,7 @@ impl FileStorageType {
         }
     }
 
+    /// Converts the `FileStorageType` into its corresponding byte value.
     pub fn to_u8(self) -> u8 {
         match self {
             FileStorageType::Store => 0,
@@ -107,7 +111,7 @@ impl FileStorageType {
 }
 
 /// File entry of an SGA archive.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SgaFileEntry {
     /// Offset of the file's name in the SGA archive's string blob.
     pub name_offset: u32,
@@ -160,4 +164,43 @@ impl SgaFileEntry {
             crc,
         })
     }
+
+    /// Size of an entry in the header blob.
+    pub const SIZE: u32 = 30;
+
+    /// The CRC32 checksum of the stored data, if the file is verified with one.
+    /// The `crc` of files with another verification type isn't checked by anything, so it may not match their data.
+    pub fn verified_crc(&self) -> Option<u32> {
+        match self.verification_type {
+            FileVerificationType::CRC => Some(self.crc),
+            _ => None,
+        }
+    }
+
+    /// Whether `other` stores data of the same size the same way, going by the sizes, storage type and verification type.
+    pub fn same_storage(&self, other: &SgaFileEntry) -> bool {
+        self.compressed_length == other.compressed_length
+            && self.uncompressed_size == other.uncompressed_size
+            && self.storage_type == other.storage_type
+            && self.verification_type == other.verification_type
+    }
+
+    /// Whether `other` stores the same data the same way, going by the verified CRC and `same_storage`.
+    /// Offsets are ignored, so entries of different archives can be compared. Entries without a verified CRC
+    /// are never the same, as nothing tells their data apart, so their stored data has to be compared instead.
+    pub fn same_data(&self, other: &SgaFileEntry) -> bool {
+        self.verified_crc().is_some() && self.verified_crc() == other.verified_crc() && self.same_storage(other)
+    }
+
+    /// Writes the entry, the inverse of `parse`.
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_u32::<LittleEndian>(self.name_offset)?;
+        writer.write_u32::<LittleEndian>(self.hash_offset)?;
+        writer.write_u64::<LittleEndian>(self.data_offset)?;
+        writer.write_u32::<LittleEndian>(self.compressed_length)?;
+        writer.write_u32::<LittleEndian>(self.uncompressed_size)?;
+        writer.write_u8(self.verification_type.to_u8())?;
+        writer.write_u8(self.storage_type.to_u8())?;
+        writer.write_u32::<LittleEndian>(self.crc)
+    }
 }
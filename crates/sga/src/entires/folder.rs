@@ -1,10 +1,13 @@
-use std::io::{BufRead, Read};
+use std::io::{self, BufRead, Read, Write};
+
+use byteorder::{LittleEndian, WriteBytesExt};
 
 use sga_macros::read_field;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 /// Folder entry of an SGA archive.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SgaFolderEntry {
     /// Offset of the folder's name in the SGA archive's string blob.
     pub name_offset: u32,
@@ -44,4 +47,16 @@ impl SgaFolderEntry {
             file_end_index,
         })
     }
+
+    /// Size of an entry in the header blob.
+    pub const SIZE: u32 = 20;
+
+    /// Writes the entry, the inverse of `parse`.
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_u32::<LittleEndian>(self.name_offset)?;
+        writer.write_u32::<LittleEndian>(self.folder_start_index)?;
+        writer.write_u32::<LittleEndian>(self.folder_end_index)?;
+        writer.write_u32::<LittleEndian>(self.file_start_index)?;
+        writer.write_u32::<LittleEndian>(self.file_end_index)
+    }
 }
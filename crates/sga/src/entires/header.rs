@@ -1,14 +1,18 @@
-use std::io::{BufRead, Read, Seek, SeekFrom};
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+
+use byteorder::{LittleEndian, WriteBytesExt};
 
 use sga_macros::read_field;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use thiserror::Error;
 
-use crate::utils::read_fixed_string;
+use crate::utils::{from_hex, read_fixed_string, to_hex, write_fixed_string};
 
 /// Header of an SGA archive.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SgaHeader {
     /// Magic value of an SGA archive. Should be "_ARCHIVE".
+    #[serde(skip, default = "archive_magic")]
     pub magic: [u8; 8], // "_ARCHIVE" is 8 bytes
 
     /// Archive version.
@@ -32,6 +36,10 @@ pub struct SgaHeader {
     /// Size of the archive's data blob in bytes.
     pub data_blob_length: u64,
 
+    /// Unknown value after the data blob length, always 1 in the archives seen so far.
+    #[serde(default = "always_one")]
+    pub unknown: u32,
+
     /// Offset relative to HeaderBlobOffset where the archive's table of contents data starts.
     pub toc_data_offset: u32,
 
@@ -62,6 +70,7 @@ pub struct SgaHeader {
     /// 2048-bit (256 byte) signature of the archive.
     /// Probably using PKCS#1 in official archives.
     /// Also validated in the game by XORing together 16 byte chunks and comparing against known values.
+    #[serde(serialize_with = "serialize_signature", deserialize_with = "deserialize_signature")]
     pub signature: [u8; 256],
 
     /// Offset relative to HeaderBlobOffset where the archive's file hash starts.
@@ -71,6 +80,24 @@ pub struct SgaHeader {
     pub file_hash_length: u32,
 }
 
+fn archive_magic() -> [u8; 8] {
+    *b"_ARCHIVE"
+}
+
+pub(crate) fn always_one() -> u32 {
+    1
+}
+
+fn serialize_signature<S: Serializer>(signature: &[u8; 256], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&to_hex(signature))
+}
+
+fn deserialize_signature<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 256], D::Error> {
+    let hex = String::deserialize(deserializer)?;
+    let bytes = from_hex(&hex).map_err(serde::de::Error::custom)?;
+    bytes.try_into().map_err(|bytes: Vec<u8>| serde::de::Error::invalid_length(bytes.len(), &"256 bytes"))
+}
+
 #[derive(Error, Debug)]
 pub enum SgaHeaderParseError {
     #[error("Magic value of an SGA archive. Should be \"_ARCHIVE\": `{0}`")]
@@ -113,7 +140,7 @@ impl SgaHeader {
         let data_offset = read_field!(reader, SgaHeaderParseError::FailedToParseNumber, u64)?;
         let data_blob_length = read_field!(reader, SgaHeaderParseError::FailedToParseNumber, u64)?;
 
-        let _ = read_field!(reader, SgaHeaderParseError::FailedToParseNumber, u32); // Always 1
+        let unknown = read_field!(reader, SgaHeaderParseError::FailedToParseNumber, u32)?;
 
         let mut signature = [0u8; 256];
         reader.read_exact(&mut signature).map_err(|_| {
@@ -152,6 +179,7 @@ impl SgaHeader {
             header_blob_length,
             data_offset,
             data_blob_length,
+            unknown,
             toc_data_offset,
             toc_data_count,
             folder_data_offset,
@@ -166,4 +194,45 @@ impl SgaHeader {
             signature,
         })
     }
+
+    /// Size of the header at the start of the archive, up to and including the signature.
+    pub const SIZE: u64 = 428;
+
+    /// Size of the offsets, counts and block size at the start of the header blob.
+    pub const BLOB_INFO_SIZE: u32 = 44;
+
+    /// Writes the header at the start of the archive, the inverse of the first part of `parse`.
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.magic)?;
+        writer.write_u16::<LittleEndian>(self.version)?;
+        writer.write_u16::<LittleEndian>(self.product)?;
+        write_fixed_string(writer, &self.name, 64, 2)?;
+        writer.write_u64::<LittleEndian>(self.header_blob_offset)?;
+        writer.write_u32::<LittleEndian>(self.header_blob_length)?;
+        writer.write_u64::<LittleEndian>(self.data_offset)?;
+        writer.write_u64::<LittleEndian>(self.data_blob_length)?;
+        writer.write_u32::<LittleEndian>(self.unknown)?;
+        writer.write_all(&self.signature)
+    }
+
+    /// Writes the offsets and counts at the start of the header blob, the inverse of the second part of `parse`.
+    pub fn write_blob_info<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for value in [
+            self.toc_data_offset,
+            self.toc_data_count,
+            self.folder_data_offset,
+            self.folder_data_count,
+            self.file_data_offset,
+            self.file_data_count,
+            self.string_offset,
+            self.string_length,
+            self.file_hash_offset,
+            self.file_hash_length,
+            self.block_size,
+        ] {
+            writer.write_u32::<LittleEndian>(value)?;
+        }
+
+        Ok(())
+    }
 }
@@ -1,12 +1,15 @@
-use std::io::{BufRead, Read};
+use std::io::{self, BufRead, Read, Write};
+
+use byteorder::{LittleEndian, WriteBytesExt};
 
 use sga_macros::read_field;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::utils::read_fixed_string;
+use crate::utils::{read_fixed_string, write_fixed_string};
 
 /// Table of contents entry of an SGA archive.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SgaToC {
     /// Alias of the table of contents.
     pub alias: String,
@@ -64,4 +67,18 @@ impl SgaToC {
             folder_root_index,
         })
     }
+
+    /// Size of an entry in the header blob.
+    pub const SIZE: u32 = 148;
+
+    /// Writes the entry, the inverse of `parse`.
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_fixed_string(writer, &self.alias, 64, 1)?;
+        write_fixed_string(writer, &self.name, 64, 1)?;
+        writer.write_u32::<LittleEndian>(self.folder_start_index)?;
+        writer.write_u32::<LittleEndian>(self.folder_end_index)?;
+        writer.write_u32::<LittleEndian>(self.file_start_index)?;
+        writer.write_u32::<LittleEndian>(self.file_end_index)?;
+        writer.write_u32::<LittleEndian>(self.folder_root_index)
+    }
 }
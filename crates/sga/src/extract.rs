@@ -0,0 +1,369 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Cursor, Read, Seek},
+    path::Path,
+    sync::{mpsc, Mutex},
+    thread,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::{
+    cancel::CancellableReader,
+    codec::ExactLengthReader,
+    conflict::{resolve_conflicts, Conflict},
+    journal::Journal,
+    nodes::FileNode,
+    options::{ExtractOptions, LinkMode, VerificationLevel},
+    plan::{shared_data, ExtractPlan, PlannedFile},
+    sink::ExtractSink,
+};
+
+/// What an extraction did.
+#[derive(Debug, Clone, Default)]
+pub struct ExtractReport {
+    /// The folders and files which were selected for extraction.
+    pub plan: ExtractPlan,
+
+    /// Whether the extraction only planned, without writing anything.
+    pub dry_run: bool,
+
+    /// Number of files written.
+    pub files_written: usize,
+
+    /// Number of bytes written.
+    pub bytes_written: u64,
+
+    /// Files which conflicted with a file already in the output or with another extracted file, and what was done about them.
+    pub conflicts: Vec<Conflict>,
+
+    /// Number of files left out because the journal of an interrupted extraction records them as completed.
+    pub resumed: usize,
+
+    /// Number of files written as links to an earlier file sharing their data, as `ExtractOptions::link_shared` asks.
+    /// They're counted in `files_written` and `bytes_written` too.
+    pub linked: usize,
+}
+
+/// The report of an extraction being written, and the journal completed files are recorded in.
+struct Progress<'a> {
+    report: ExtractReport,
+    journal: Option<&'a mut Journal>,
+}
+
+/// Resolves the conflicts of `plan` and writes it into `sink` unless it's a dry run, without finishing the sink.
+/// Completed files are recorded in `journal`, which is opened once the conflicts are resolved.
+pub(crate) fn run_plan<T: Read + Seek, S: ExtractSink + ?Sized>(
+    reader: &mut T,
+    mut plan: ExtractPlan,
+    sink: &mut S,
+    options: &ExtractOptions,
+    journal: Option<&mut Journal>,
+) -> Result<ExtractReport> {
+    let conflicts = resolve_conflicts(&mut plan, sink, options.overwrite).inspect_err(|err| options.observer.error(None, err))?;
+
+    let report = ExtractReport { dry_run: options.dry_run, conflicts, ..Default::default() };
+    let mut progress = Progress { report, journal };
+    if !options.dry_run {
+        if let Some(journal) = progress.journal.as_deref_mut() {
+            journal.open().inspect_err(|err| options.observer.error(None, err))?;
+        }
+        write_plan(reader, &plan, sink, options, &mut progress)?;
+    }
+
+    progress.report.plan = plan;
+    Ok(progress.report)
+}
+
+/// Writes the folders and files of `plan` into `sink`, without finishing it.
+fn write_plan<T: Read + Seek, S: ExtractSink + ?Sized>(
+    reader: &mut T,
+    plan: &ExtractPlan,
+    sink: &mut S,
+    options: &ExtractOptions,
+    progress: &mut Progress,
+) -> Result<()> {
+    let observer = options.observer.as_ref();
+
+    for folder_path in &plan.folders {
+        observer.folder_entered(folder_path);
+        sink.create_dir(folder_path)
+            .with_context(|| format!("Failed to create folder '{}'", folder_path.display()))?;
+    }
+
+    match options.jobs {
+        0 | 1 => write_files(reader, &plan.files, sink, options, progress),
+        jobs => write_files_parallel(reader, &plan.files, sink, options, progress, jobs),
+    }
+}
+
+/// Streams every file from the archive into the sink, one after the other.
+fn write_files<T: Read + Seek, S: ExtractSink + ?Sized>(
+    reader: &mut T,
+    files: &[PlannedFile],
+    sink: &mut S,
+    options: &ExtractOptions,
+    progress: &mut Progress,
+) -> Result<()> {
+    let shared = shared_positions(files, options);
+
+    for (position, planned) in files.iter().enumerate() {
+        options.check_cancelled()?;
+
+        if let Some(original) = shared[position]
+            && link_shared(sink, &files[original], planned, options, progress)?
+        {
+            continue;
+        }
+
+        let result = start_file(&planned.file, &planned.path, options)
+            .and_then(|()| open_verified(reader, &planned.file, options))
+            .and_then(|data| write_data(sink, planned, data, options));
+
+        finish_file(sink, planned, result, options, progress)?;
+    }
+
+    Ok(())
+}
+
+/// Reads the stored data of the files on the calling thread, decodes them on `jobs` threads,
+/// and writes them into the sink on the calling thread as they are decoded.
+/// At most two files per thread are held in memory at once. Files sharing their data with a file being decoded are written
+/// once it is, from its decoded data if they can't be linked to it.
+fn write_files_parallel<T: Read + Seek, S: ExtractSink + ?Sized>(
+    reader: &mut T,
+    files: &[PlannedFile],
+    sink: &mut S,
+    options: &ExtractOptions,
+    progress: &mut Progress,
+    jobs: usize,
+) -> Result<()> {
+    let max_in_flight = jobs * 2;
+    let (job_sender, job_receiver) = mpsc::channel::<(usize, Vec<u8>)>();
+    let (done_sender, done_receiver) = mpsc::channel::<(usize, Result<Vec<u8>>)>();
+    let job_receiver = Mutex::new(job_receiver);
+    let shared = shared_positions(files, options);
+    let originals: HashSet<usize> = shared.iter().flatten().copied().collect();
+    // The files waiting for each original being decoded which shares its data with them.
+    let mut waiting: HashMap<usize, Vec<usize>> = HashMap::new();
+
+    thread::scope(|scope| {
+        // Dropped when the loop below returns, which stops the threads before the scope waits for them.
+        let job_sender = job_sender;
+
+        for _ in 0..jobs {
+            let job_receiver = &job_receiver;
+            let done_sender = done_sender.clone();
+
+            scope.spawn(move || {
+                loop {
+                    let job = job_receiver.lock().unwrap().recv();
+                    let Ok((index, stored)) = job else {
+                        break;
+                    };
+
+                    let decoded = decode_stored(&files[index].file, stored, options);
+                    if done_sender.send((index, decoded)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(done_sender);
+
+        let mut next = 0;
+        let mut in_flight = 0;
+
+        loop {
+            while in_flight < max_in_flight && next < files.len() {
+                let planned = &files[next];
+                options.check_cancelled()?;
+
+                if let Some(original) = shared[next] {
+                    match waiting.get_mut(&original) {
+                        Some(duplicates) => {
+                            duplicates.push(next);
+                            next += 1;
+                            continue;
+                        }
+                        // The original is already written, the file is decoded again if it can't be linked to it.
+                        None if link_shared(sink, &files[original], planned, options, progress)? => {
+                            next += 1;
+                            continue;
+                        }
+                        None => {}
+                    }
+                }
+
+                let stored = start_file(&planned.file, &planned.path, options)
+                    .and_then(|()| read_verified(reader, &planned.file, options));
+
+                match stored {
+                    Ok(stored) => {
+                        job_sender.send((next, stored)).map_err(|_| anyhow!("The decoding threads stopped unexpectedly"))?;
+                        in_flight += 1;
+                        if originals.contains(&next) {
+                            waiting.insert(next, Vec::new());
+                        }
+                    }
+                    Err(err) => finish_file(sink, planned, Err(err), options, progress)?,
+                }
+
+                next += 1;
+            }
+
+            if in_flight == 0 {
+                return Ok(());
+            }
+
+            let (index, decoded) = done_receiver.recv()?;
+            in_flight -= 1;
+
+            let planned = &files[index];
+            let data = match decoded {
+                Ok(data) => data,
+                Err(err) => return finish_file(sink, planned, Err(err), options, progress),
+            };
+            let result = write_data(sink, planned, Box::new(Cursor::new(&data[..])), options);
+            finish_file(sink, planned, result, options, progress)?;
+
+            for duplicate in waiting.remove(&index).unwrap_or_default() {
+                let duplicate = &files[duplicate];
+                if !link_shared(sink, planned, duplicate, options, progress)? {
+                    let result = start_file(&duplicate.file, &duplicate.path, options)
+                        .and_then(|()| write_data(sink, duplicate, Box::new(Cursor::new(&data[..])), options));
+                    finish_file(sink, duplicate, result, options, progress)?;
+                }
+            }
+        }
+    })
+}
+
+/// For every file, the position of the earlier file sharing its data, if the files sharing data are linked.
+fn shared_positions(files: &[PlannedFile], options: &ExtractOptions) -> Vec<Option<usize>> {
+    match options.link {
+        LinkMode::None => vec![None; files.len()],
+        _ => shared_data(files),
+    }
+}
+
+/// Writes `planned` as a link to the already written `original`, which shares its data.
+/// Returns false if the sink can't link files, so it's written as usual.
+fn link_shared<S: ExtractSink + ?Sized>(
+    sink: &mut S,
+    original: &PlannedFile,
+    planned: &PlannedFile,
+    options: &ExtractOptions,
+    progress: &mut Progress,
+) -> Result<bool> {
+    let result = match sink.link_file(&original.path, &planned.path, options.link) {
+        Ok(false) => return Ok(false),
+        Ok(true) => Ok(planned.file.output_size(options.decoding_codecs()) as u64),
+        Err(err) => Err(anyhow!(err).context(format!("Failed to link it to '{}'", original.path.display()))),
+    };
+
+    options.observer.file_started(&planned.path, planned.file.size() as u64);
+    finish_file(sink, planned, result, options, progress)?;
+    progress.report.linked += 1;
+    Ok(true)
+}
+
+/// Notifies the observer about a file which is about to be extracted.
+fn start_file(file_node: &FileNode, file_path: &Path, options: &ExtractOptions) -> Result<()> {
+    let observer = options.observer.as_ref();
+    observer.file_started(file_path, file_node.size() as u64);
+
+    if !options.raw && !options.codecs.contains(file_node.storage_type) {
+        observer.warning(Some(file_path), &format!(
+            "The storage type is unknown with value of '{}', it will be unpacked as raw bytes!",
+            file_node.storage_type.to_u8()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Opens the decoded data of `file_node`, checked according to the verification level.
+fn open_verified<'a, T: Read + Seek>(reader: &'a mut T, file_node: &FileNode, options: &ExtractOptions) -> Result<Box<dyn Read + 'a>> {
+    match options.verification {
+        VerificationLevel::None => file_node.decode(file_node.open_stored(reader)?, options.decoding_codecs()),
+        VerificationLevel::Length => file_node.open_data(reader, options.decoding_codecs()),
+        VerificationLevel::Crc => {
+            let stored = read_verified(reader, file_node, options)?;
+            let decoded = file_node.decode(Box::new(Cursor::new(stored)), options.decoding_codecs())?;
+            Ok(Box::new(ExactLengthReader::new(decoded, file_node.output_size(options.decoding_codecs()) as u64)))
+        }
+    }
+}
+
+/// Reads the stored data of `file_node`, checking its CRC if the verification level asks for it.
+fn read_verified<T: Read + Seek>(reader: &mut T, file_node: &FileNode, options: &ExtractOptions) -> Result<Vec<u8>> {
+    let stored = file_node.read_stored(reader)?;
+
+    if options.verification == VerificationLevel::Crc
+        && let Some(expected) = file_node.crc()
+    {
+        let actual = crc32fast::hash(&stored);
+        if actual != expected {
+            bail!("CRC mismatch, expected {:#010x} but the data has {:#010x}", expected, actual);
+        }
+    }
+
+    Ok(stored)
+}
+
+/// Decodes the `stored` data of `file_node` into memory.
+fn decode_stored(file_node: &FileNode, stored: Vec<u8>, options: &ExtractOptions) -> Result<Vec<u8>> {
+    let mut decoded = file_node.decode(Box::new(Cursor::new(stored)), options.decoding_codecs())?;
+    if options.verification != VerificationLevel::None {
+        decoded = Box::new(ExactLengthReader::new(decoded, file_node.output_size(options.decoding_codecs()) as u64));
+    }
+
+    let mut data = Vec::with_capacity(file_node.output_size(options.decoding_codecs()));
+    CancellableReader::new(decoded, options.cancellation.as_ref()).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Writes the decoded `data` of a file into the sink.
+fn write_data<S: ExtractSink + ?Sized>(sink: &mut S, planned: &PlannedFile, data: Box<dyn Read + '_>, options: &ExtractOptions) -> Result<u64> {
+    let mut data = CancellableReader::new(data, options.cancellation.as_ref());
+    let size = planned.file.output_size(options.decoding_codecs()) as u64;
+    Ok(sink.write_file(&planned.path, size, &mut data)?)
+}
+
+/// Reports the outcome of extracting a file, cleaning up after it if it failed.
+fn finish_file<S: ExtractSink + ?Sized>(
+    sink: &mut S,
+    planned: &PlannedFile,
+    result: Result<u64>,
+    options: &ExtractOptions,
+    progress: &mut Progress,
+) -> Result<()> {
+    let observer = options.observer.as_ref();
+
+    let err = match result {
+        Ok(bytes_written) => {
+            observer.file_finished(&planned.path, bytes_written);
+            progress.report.files_written += 1;
+            progress.report.bytes_written += bytes_written;
+
+            if let Some(journal) = progress.journal.as_deref_mut() {
+                journal.record(&planned.file).context("Failed to write the journal")?;
+            }
+            return Ok(());
+        }
+        Err(err) => err,
+    };
+
+    if options.remove_partial_files {
+        let _ = sink.remove_file(&planned.path);
+    }
+
+    let err = match options.check_cancelled() {
+        Err(cancelled) => cancelled.into(),
+        Ok(()) => err.context(format!("Failed to extract '{}'", planned.path.display())),
+    };
+
+    observer.error(Some(&planned.path), &err);
+    Err(err)
+}
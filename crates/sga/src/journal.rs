@@ -0,0 +1,166 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+use crate::{entires::SgaHeader, nodes::FileNode, plan::{ExtractPlan, PlannedFile}, sink::ExtractSink};
+
+/// Name of the journal file, written into the root of the output folder.
+pub(crate) const JOURNAL_FILE_NAME: &str = ".sga-extract.journal";
+
+/// Records the files an extraction into a folder has completed, so an interrupted extraction can be resumed.
+///
+/// The first line identifies the archive, every other line holds the index and CRC of a completed file entry.
+/// Nothing is written until `open` is called, right before the first file is extracted.
+/// The journal is removed once the extraction succeeds, or if it fails before completing any file.
+#[derive(Debug)]
+pub(crate) struct Journal {
+    path: PathBuf,
+    id: String,
+    /// Whether the journal already in the folder is continued rather than replaced.
+    append: bool,
+    file: Option<File>,
+    recorded: usize,
+}
+
+impl Journal {
+    /// Prepares a new journal in `folder`, which replaces any previous one once it's opened.
+    pub(crate) fn create(folder: &Path, header: &SgaHeader) -> Self {
+        Self { path: folder.join(JOURNAL_FILE_NAME), id: archive_id(header), append: false, file: None, recorded: 0 }
+    }
+
+    /// Prepares to continue the journal in `folder`, returning it with the completed files it records.
+    /// A new journal is started if there is none, or if it was written for another archive.
+    pub(crate) fn resume(folder: &Path, header: &SgaHeader) -> Result<(Self, HashSet<(usize, u32)>)> {
+        let mut journal = Self::create(folder, header);
+        match Self::read(folder, header)? {
+            Some(completed) => {
+                journal.append = true;
+                Ok((journal, completed))
+            }
+            None => Ok((journal, HashSet::new())),
+        }
+    }
+
+    /// Creates or continues the journal file, along with the folder it's in.
+    pub(crate) fn open(&mut self) -> Result<()> {
+        if self.file.is_some() {
+            return Ok(());
+        }
+
+        let file = match self.append {
+            true => OpenOptions::new().append(true).open(&self.path),
+            false => {
+                if let Some(folder) = self.path.parent() {
+                    fs::create_dir_all(folder)?;
+                }
+                File::create(&self.path).and_then(|mut file| writeln!(file, "{}", self.id).map(|()| file))
+            }
+        };
+        self.file = Some(file.with_context(|| format!("Failed to create the journal '{}'", self.path.display()))?);
+
+        Ok(())
+    }
+
+    /// Reads the completed files recorded by the journal in `folder`,
+    /// or `None` if there is no journal or it was written for another archive.
+    pub(crate) fn read(folder: &Path, header: &SgaHeader) -> Result<Option<HashSet<(usize, u32)>>> {
+        let path = folder.join(JOURNAL_FILE_NAME);
+
+        match File::open(&path) {
+            Ok(file) => read_completed(BufReader::new(file), header)
+                .with_context(|| format!("Failed to read the journal '{}'", path.display())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Records that `file` was completely written.
+    pub(crate) fn record(&mut self, file: &FileNode) -> io::Result<()> {
+        let (Some(journal), Some(index), Some(entry)) = (self.file.as_mut(), file.index(), file.entry()) else {
+            return Ok(());
+        };
+
+        writeln!(journal, "{} {:08x}", index, entry.crc)?;
+        self.recorded += 1;
+        Ok(())
+    }
+
+    /// Removes the journal once the extraction is complete.
+    pub(crate) fn remove(self) -> io::Result<()> {
+        match self.file {
+            Some(file) => {
+                drop(file);
+                fs::remove_file(&self.path)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Removes the journal after the extraction failed, unless it records completed files it can be resumed from.
+    pub(crate) fn abandon(self) -> io::Result<()> {
+        match self.append || self.recorded > 0 {
+            true => Ok(()),
+            false => self.remove(),
+        }
+    }
+}
+
+/// Identifies the archive a journal was written for.
+fn archive_id(header: &SgaHeader) -> String {
+    format!(
+        "sga-extract-journal 1 {} {} {} {} {}",
+        header.version, header.header_blob_offset, header.header_blob_length, header.data_blob_length, header.name
+    )
+}
+
+/// Reads the completed files of a journal, or `None` if it belongs to another archive.
+/// A last line cut off by an interruption is ignored.
+fn read_completed<R: BufRead>(reader: R, header: &SgaHeader) -> Result<Option<HashSet<(usize, u32)>>> {
+    let mut lines = reader.lines();
+    if lines.next().transpose()?.as_deref() != Some(archive_id(header).as_str()) {
+        return Ok(None);
+    }
+
+    let mut completed = HashSet::new();
+    for line in lines {
+        let line = line?;
+        let parsed = line
+            .split_once(' ')
+            .and_then(|(index, crc)| Some((index.parse().ok()?, u32::from_str_radix(crc, 16).ok()?)));
+
+        if let Some(entry) = parsed {
+            completed.insert(entry);
+        }
+    }
+
+    Ok(Some(completed))
+}
+
+/// Leaves out of `plan` the files recorded as `completed` which are still in `sink`, and returns them.
+pub(crate) fn skip_completed<S: ExtractSink + ?Sized>(plan: &mut ExtractPlan, sink: &S, completed: &HashSet<(usize, u32)>) -> io::Result<Vec<PlannedFile>> {
+    let mut skipped = Vec::new();
+    if completed.is_empty() {
+        return Ok(skipped);
+    }
+
+    let mut kept = Vec::with_capacity(plan.files.len());
+    for planned in plan.files.drain(..) {
+        let is_completed = match (planned.file.index(), planned.file.entry()) {
+            (Some(index), Some(entry)) => completed.contains(&(index, entry.crc)) && sink.exists(&planned.path)?,
+            _ => false,
+        };
+
+        match is_completed {
+            true => skipped.push(planned),
+            false => kept.push(planned),
+        }
+    }
+
+    plan.files = kept;
+    Ok(skipped)
+}
@@ -0,0 +1,198 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Read, Seek, SeekFrom},
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    entires::{SgaEntries, SgaFileEntry, SgaFolderEntry, SgaHeader, SgaToC},
+    utils::{from_hex, to_hex},
+};
+
+/// Everything of an archive besides the data of its files: the header, every entry in archive order,
+/// and the string and hash blobs. With the stored data of every file entry, it's enough to write the archive again byte for byte.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveLayout {
+    /// The header of the archive, with the offsets of everything else.
+    pub header: SgaHeader,
+
+    /// Every table of contents entry, in archive order.
+    pub tocs: Vec<SgaToC>,
+
+    /// Every folder entry, in archive order.
+    pub folders: Vec<SgaFolderEntry>,
+
+    /// Every file entry, in archive order.
+    pub files: Vec<SgaFileEntry>,
+
+    /// The strings of the string blob in the order they're stored, each one is followed by a NUL in the blob.
+    pub strings: Vec<String>,
+
+    /// The hash blob, as a hexadecimal string.
+    pub file_hashes: String,
+}
+
+impl ArchiveLayout {
+    /// Reads the string and hash blobs of the archive with the given `entries` from `reader`.
+    pub fn read<R: Read + Seek>(reader: &mut R, entries: &SgaEntries) -> Result<Self> {
+        let header = &entries.header;
+
+        let string_blob = read_blob(reader, header, header.string_offset, header.string_length)
+            .context("Failed to read the string blob")?;
+        let strings = split_strings(&string_blob)?;
+
+        let file_hashes = read_blob(reader, header, header.file_hash_offset, header.file_hash_length)
+            .context("Failed to read the hash blob")?;
+
+        Ok(Self {
+            header: header.clone(),
+            tocs: entries.tocs.clone(),
+            folders: entries.folders.clone(),
+            files: entries.files.clone(),
+            strings,
+            file_hashes: to_hex(&file_hashes),
+        })
+    }
+
+    /// The string blob, every string followed by a NUL.
+    pub fn string_blob(&self) -> Vec<u8> {
+        let mut blob = Vec::new();
+        for string in &self.strings {
+            blob.extend_from_slice(string.as_bytes());
+            blob.push(0);
+        }
+        blob
+    }
+
+    /// Every string of the string blob, keyed by its offset in the blob.
+    pub fn strings_by_offset(&self) -> HashMap<u32, &str> {
+        let mut offset = 0;
+        let mut strings = HashMap::with_capacity(self.strings.len());
+        for string in &self.strings {
+            strings.insert(offset, string.as_str());
+            offset += string.len() as u32 + 1;
+        }
+        strings
+    }
+
+    /// Size of the stored data file entries share with an earlier entry, which would be stored again if they didn't share it.
+    pub fn shared_bytes(&self) -> u64 {
+        let mut seen = HashSet::new();
+        self.files
+            .iter()
+            .filter(|entry| !seen.insert((entry.data_offset, entry.compressed_length)))
+            .map(|entry| entry.compressed_length as u64)
+            .sum()
+    }
+
+    /// The hash blob.
+    pub fn hash_blob(&self) -> Result<Vec<u8>> {
+        from_hex(&self.file_hashes).context("Invalid hash blob")
+    }
+
+    /// Places the sections of the header blob one after another, in the order they're parsed,
+    /// and sets their offsets, counts and lengths in the header.
+    pub fn arrange_header_blob(&mut self) -> Result<()> {
+        let string_length = self.string_blob().len() as u32;
+        let hash_length = self.hash_blob()?.len() as u32;
+        let header = &mut self.header;
+
+        header.toc_data_offset = SgaHeader::BLOB_INFO_SIZE;
+        header.toc_data_count = self.tocs.len() as u32;
+        header.folder_data_offset = header.toc_data_offset + header.toc_data_count * SgaToC::SIZE;
+        header.folder_data_count = self.folders.len() as u32;
+        header.file_data_offset = header.folder_data_offset + header.folder_data_count * SgaFolderEntry::SIZE;
+        header.file_data_count = self.files.len() as u32;
+        header.string_offset = header.file_data_offset + header.file_data_count * SgaFileEntry::SIZE;
+        header.string_length = string_length;
+        header.file_hash_offset = header.string_offset + string_length;
+        header.file_hash_length = hash_length;
+        header.header_blob_length = header.file_hash_offset + hash_length;
+
+        Ok(())
+    }
+
+    /// The header blob, with every section at the offset the header gives it and zeros between them.
+    pub fn header_blob(&self) -> Result<Vec<u8>> {
+        let header = &self.header;
+        let string_blob = self.string_blob();
+        let hash_blob = self.hash_blob()?;
+
+        for (what, recorded, actual) in [
+            ("table of contents entries", header.toc_data_count as usize, self.tocs.len()),
+            ("folder entries", header.folder_data_count as usize, self.folders.len()),
+            ("file entries", header.file_data_count as usize, self.files.len()),
+            ("bytes of strings", header.string_length as usize, string_blob.len()),
+            ("bytes of hashes", header.file_hash_length as usize, hash_blob.len()),
+        ] {
+            if recorded != actual {
+                bail!("The header records {} {}, but the layout has {}", recorded, what, actual);
+            }
+        }
+
+        let mut sections = vec![(0, "blob info", Vec::new())];
+        header.write_blob_info(&mut sections[0].2)?;
+        sections.push((header.toc_data_offset, "table of contents entries", write_entries(&self.tocs, SgaToC::write)?));
+        sections.push((header.folder_data_offset, "folder entries", write_entries(&self.folders, SgaFolderEntry::write)?));
+        sections.push((header.file_data_offset, "file entries", write_entries(&self.files, SgaFileEntry::write)?));
+        sections.push((header.string_offset, "string blob", string_blob));
+        sections.push((header.file_hash_offset, "hash blob", hash_blob));
+        sections.retain(|(_, _, bytes)| !bytes.is_empty());
+        sections.sort_by_key(|(offset, _, _)| *offset);
+
+        let mut blob = Vec::with_capacity(header.header_blob_length as usize);
+        for (offset, what, bytes) in sections {
+            let offset = offset as usize;
+            if offset < blob.len() {
+                bail!("The {} at offset {} overlap what comes before them in the header blob", what, offset);
+            }
+            blob.resize(offset, 0);
+            blob.extend_from_slice(&bytes);
+        }
+
+        if blob.len() > header.header_blob_length as usize {
+            bail!("The header blob needs {} bytes, but the header records {}", blob.len(), header.header_blob_length);
+        }
+        blob.resize(header.header_blob_length as usize, 0);
+
+        Ok(blob)
+    }
+}
+
+/// Reads `length` bytes at `offset` in the header blob.
+fn read_blob<R: Read + Seek>(reader: &mut R, header: &SgaHeader, offset: u32, length: u32) -> Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(header.header_blob_offset + offset as u64))?;
+    let mut blob = vec![0; length as usize];
+    reader.read_exact(&mut blob)?;
+    Ok(blob)
+}
+
+/// Splits a string blob into its NUL terminated strings.
+fn split_strings(blob: &[u8]) -> Result<Vec<String>> {
+    let Some(strings) = blob.strip_suffix(&[0]) else {
+        match blob.is_empty() {
+            true => return Ok(Vec::new()),
+            false => bail!("The string blob doesn't end with a NUL, it can't be recorded as strings"),
+        }
+    };
+
+    strings
+        .split(|&byte| byte == 0)
+        .map(|string| String::from_utf8(string.to_vec()).context("The string blob holds a name which isn't valid UTF-8"))
+        .collect()
+}
+
+/// Writes every entry one after another.
+fn write_entries<T, W>(entries: &[T], write: W) -> Result<Vec<u8>>
+where
+    W: Fn(&T, &mut Vec<u8>) -> std::io::Result<()>,
+{
+    let mut bytes = Vec::new();
+    for entry in entries {
+        write(entry, &mut bytes)?;
+    }
+    Ok(bytes)
+}
+
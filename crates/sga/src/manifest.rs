@@ -0,0 +1,201 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    entires::{FileStorageType, FileVerificationType, SgaEntries},
+    layout::ArchiveLayout,
+    plan::ExtractPlan,
+    sink::to_archive_path,
+};
+
+#[derive(Error, Debug)]
+pub enum ManifestError {
+    #[error("Failed to read or write the manifest: {0}")]
+    Io(#[from] io::Error),
+    #[error("Invalid JSON manifest: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid TOML manifest: {0}")]
+    TomlParse(#[from] toml::de::Error),
+    #[error("Failed to write the TOML manifest: {0}")]
+    TomlWrite(#[from] toml::ser::Error),
+    #[error("Unknown manifest format for '{0}', expected a .json or .toml file")]
+    UnknownFormat(PathBuf),
+}
+
+/// The file format of a manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestFormat {
+    Json,
+    Toml,
+}
+
+impl ManifestFormat {
+    /// Name of the manifest file written next to the extracted files.
+    pub fn file_name(self) -> &'static str {
+        match self {
+            ManifestFormat::Json => "sga-manifest.json",
+            ManifestFormat::Toml => "sga-manifest.toml",
+        }
+    }
+
+    /// Guesses the format from the extension of `path`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_string_lossy().to_lowercase();
+        match extension.as_str() {
+            "json" => Some(ManifestFormat::Json),
+            "toml" => Some(ManifestFormat::Toml),
+            _ => None,
+        }
+    }
+}
+
+/// The archive metadata of an extraction, which is lost in the extracted files themselves.
+/// A packer can read it to rebuild an archive with the same settings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Version of the archive format.
+    pub version: u16,
+
+    /// Product the archive was made for.
+    pub product: u16,
+
+    /// Name of the archive.
+    pub name: String,
+
+    /// Block size from the header.
+    pub block_size: u32,
+
+    /// Every table of contents of the archive, in archive order.
+    pub tocs: Vec<ManifestToc>,
+
+    /// The extracted files, in extraction order.
+    pub files: Vec<ManifestFile>,
+
+    /// The layout of the archive, recorded by raw extractions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout: Option<ArchiveLayout>,
+}
+
+/// A table of contents of the archive.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestToc {
+    /// Alias of the table of contents, for example `data`.
+    pub alias: String,
+
+    /// Name of the table of contents.
+    pub name: String,
+}
+
+/// The metadata of an extracted file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestFile {
+    /// Path the file was extracted to, relative to the output and separated by `/`.
+    pub path: String,
+
+    /// Alias of the table of contents the file belongs to.
+    pub toc: String,
+
+    /// Path of the file inside its table of contents, separated by `/`.
+    pub archive_path: String,
+
+    /// Index of the file in the archive's file entries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+
+    /// Position of the file's data in the data blob, `0` for the file whose data comes first.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_order: Option<usize>,
+
+    /// How the file's data is stored.
+    pub storage_type: FileStorageType,
+
+    /// How the file is verified when it's loaded.
+    pub verification_type: FileVerificationType,
+
+    /// CRC32 checksum from the file's entry.
+    pub crc: u32,
+
+    /// Size of the file once decoded, in bytes.
+    pub size: u64,
+
+    /// Size of the file as stored in the archive, in bytes.
+    pub stored_size: u64,
+}
+
+impl Manifest {
+    /// Describes the files of `plan`, extracted from the archive with the given `entries`.
+    pub fn from_plan(entries: &SgaEntries, plan: &ExtractPlan) -> Self {
+        let mut by_offset: Vec<usize> = (0..entries.files.len()).collect();
+        by_offset.sort_by_key(|&index| (entries.files[index].data_offset, index));
+
+        let mut data_order = vec![0; entries.files.len()];
+        for (order, index) in by_offset.into_iter().enumerate() {
+            data_order[index] = order;
+        }
+
+        let files = plan
+            .files
+            .iter()
+            .map(|planned| {
+                let file = &planned.file;
+                let entry = file.entry();
+
+                ManifestFile {
+                    path: to_archive_path(&planned.path),
+                    toc: planned.toc.clone(),
+                    archive_path: planned.archive_path.clone(),
+                    index: file.index(),
+                    data_order: file.index().and_then(|index| data_order.get(index).copied()),
+                    storage_type: file.storage_type,
+                    verification_type: entry.map_or(FileVerificationType::None, |entry| entry.verification_type),
+                    crc: entry.map_or(0, |entry| entry.crc),
+                    size: file.size() as u64,
+                    stored_size: file.stored_size() as u64,
+                }
+            })
+            .collect();
+
+        let header = &entries.header;
+        Self {
+            version: header.version,
+            product: header.product,
+            name: header.name.clone(),
+            block_size: header.block_size,
+            tocs: entries
+                .tocs
+                .iter()
+                .map(|toc| ManifestToc { alias: toc.alias.clone(), name: toc.name.clone() })
+                .collect(),
+            files,
+            layout: None,
+        }
+    }
+
+    /// Serializes the manifest in `format`.
+    pub fn to_string(&self, format: ManifestFormat) -> Result<String, ManifestError> {
+        Ok(match format {
+            ManifestFormat::Json => serde_json::to_string_pretty(self)?,
+            ManifestFormat::Toml => toml::to_string_pretty(self)?,
+        })
+    }
+
+    /// Parses a manifest written in `format`.
+    pub fn parse(text: &str, format: ManifestFormat) -> Result<Self, ManifestError> {
+        Ok(match format {
+            ManifestFormat::Json => serde_json::from_str(text)?,
+            ManifestFormat::Toml => toml::from_str(text)?,
+        })
+    }
+
+    /// Reads the manifest at `path`, in the format given by its extension.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, ManifestError> {
+        let path = path.as_ref();
+        let format = ManifestFormat::from_path(path).ok_or_else(|| ManifestError::UnknownFormat(path.to_path_buf()))?;
+        Self::parse(&fs::read_to_string(path)?, format)
+    }
+}
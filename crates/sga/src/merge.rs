@@ -0,0 +1,142 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{BufRead, Read, Seek, Write},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    archive::SgaArchive,
+    builder::ArchiveBuilder,
+    options::ExtractOptions,
+    subset::copy_stored,
+};
+
+/// Which file `merge` keeps when several archives have a file at the same path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Precedence {
+    /// The file of the archive given last is kept, the way overrides are usually loaded after the archives they override.
+    #[default]
+    Last,
+
+    /// The file of the archive given first is kept.
+    First,
+}
+
+/// A file of one archive which replaced the file at the same path of another archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Override {
+    /// Alias of the table of contents holding the file.
+    pub toc: String,
+
+    /// Path of the file inside its table of contents, separated by `/`, as it's written in the kept archive.
+    pub path: String,
+
+    /// Position of the archive whose file is kept.
+    pub kept: usize,
+
+    /// Position of the archive whose file was replaced.
+    pub replaced: usize,
+}
+
+impl fmt::Display for Override {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}: archive {} overrides archive {}", self.toc, self.path, self.kept, self.replaced)
+    }
+}
+
+/// What `merge` wrote.
+#[derive(Debug, Clone, Default)]
+pub struct MergeReport {
+    /// Number of tables of contents in the merged archive.
+    pub tocs: usize,
+
+    /// Number of files in the merged archive.
+    pub files: usize,
+
+    /// Every file which was replaced by the file of another archive, in the order they were found.
+    pub overrides: Vec<Override>,
+
+    /// Size of the data blob of the merged archive.
+    pub stored_bytes: u64,
+
+    /// Size of the stored data several files share, which is only stored once.
+    pub shared_bytes: u64,
+}
+
+/// Writes the files of every archive of `archives` into a single archive.
+///
+/// Tables of contents with the same alias are merged into one, named like the first of them. When several archives have
+/// a file at the same path inside a table of contents, ignoring case, `precedence` chooses which one is kept and the
+/// other is listed in the `overrides` of the report. Files of the same archive are all kept. The stored data of the files is copied as is.
+/// The header of the merged archive comes from the first archive.
+pub fn merge<R, W>(archives: &mut [SgaArchive<R>], writer: W, precedence: Precedence) -> Result<MergeReport>
+where
+    R: Read + BufRead + Seek,
+    W: Write + Seek,
+{
+    let Some(first) = archives.first() else {
+        bail!("There are no archives to merge");
+    };
+
+    let mut builder = ArchiveBuilder::with_header(&first.entries.header);
+    // The archive holding the files at each path ignoring case, with how they're spelled.
+    let mut paths: HashMap<(String, String), (usize, Vec<String>)> = HashMap::new();
+    let mut overrides = Vec::new();
+
+    for (position, archive) in archives.iter().enumerate() {
+        let plan = archive
+            .plan(&ExtractOptions::default())
+            .with_context(|| format!("Failed to read the files of archive {}", position))?;
+
+        for planned in &plan.files {
+            let index = planned.file.index().with_context(|| format!("'{}' has no file entry", planned.archive_path))?;
+            let toc_name = archive.toc(&planned.toc).map_or(planned.toc.as_str(), |toc| toc.name.as_str());
+            builder.add_toc(&planned.toc, toc_name);
+
+            let key = (planned.toc.to_lowercase(), planned.archive_path.to_lowercase());
+            let (owner, spellings) = paths.entry(key).or_insert_with(|| (position, Vec::new()));
+
+            if *owner != position {
+                let kept = match precedence {
+                    Precedence::Last => position,
+                    Precedence::First => *owner,
+                };
+                overrides.push(Override {
+                    toc: planned.toc.clone(),
+                    path: match kept == position {
+                        true => planned.archive_path.clone(),
+                        false => spellings[0].clone(),
+                    },
+                    kept,
+                    replaced: match kept == position {
+                        true => *owner,
+                        false => position,
+                    },
+                });
+
+                if precedence == Precedence::First {
+                    continue;
+                }
+                for spelling in spellings.drain(..) {
+                    builder.remove_file(&planned.toc, spelling);
+                }
+                *owner = position;
+            }
+
+            spellings.push(planned.archive_path.clone());
+            builder.add_file(&planned.toc, &planned.archive_path, (position, index));
+        }
+    }
+
+    let layout = builder.write(writer, |&(position, index)| copy_stored(&mut archives[position], index))?;
+
+    Ok(MergeReport {
+        tocs: layout.tocs.len(),
+        files: layout.files.len(),
+        overrides,
+        stored_bytes: layout.header.data_blob_length,
+        shared_bytes: layout.shared_bytes(),
+    })
+}
@@ -1,10 +1,8 @@
 use std::{io::{BufRead, Read, Seek, SeekFrom}, sync::{Arc, Mutex}};
 
 use anyhow::Result;
-use brotli::Decompressor;
-use flate2::read::DeflateDecoder;
 
-use crate::{entires::{FileStorageType, SgaEntries, SgaFileEntry}, utils::read_c_string};
+use crate::{codec::{check_length, CodecRegistry, ExactLengthReader}, entires::{FileStorageType, SgaEntries, SgaFileEntry}, utils::read_c_string};
 
 use super::FolderNode;
 
@@ -23,6 +21,9 @@ pub struct FileNode {
 
     /// The type of file storage it is
     pub storage_type: FileStorageType,
+
+    index: Option<usize>,
+    entry: Option<SgaFileEntry>,
 }
 
 impl FileNode {
@@ -30,42 +31,119 @@ impl FileNode {
     pub fn new<U: AsRef<str>>(name: U, data_position: u64, data_length: usize, data_uncompressed_length: usize, storage_type: FileStorageType, parent: Arc<Mutex<FolderNode>>) -> Self {
         Self {
             name: name.as_ref().to_string(),
-            parent: parent,
+            parent,
 
             data_position,
             data_length,
             data_uncompressed_length,
 
-            storage_type
+            storage_type,
+
+            index: None,
+            entry: None,
         }
     }
 
-    /// Reads the data from the file and returns it as a vector. 
+    /// Attaches the entry the file was read from, and its index in the archive's file entries.
+    pub fn with_entry(mut self, index: usize, entry: SgaFileEntry) -> Self {
+        self.index = Some(index);
+        self.entry = Some(entry);
+        self
+    }
+
+    /// Index of the file in the archive's file entries, if it was read from an archive.
+    pub fn index(&self) -> Option<usize> {
+        self.index
+    }
+
+    /// The entry the file was read from, if it was read from an archive.
+    pub fn entry(&self) -> Option<&SgaFileEntry> {
+        self.entry.as_ref()
+    }
+
+    /// CRC32 checksum of the stored data, if the file is verified with one.
+    pub fn crc(&self) -> Option<u32> {
+        self.entry.as_ref().and_then(SgaFileEntry::verified_crc)
+    }
+
+    /// Whether `other` is stored as the same data at the same position of the archive, so both decode to the same bytes.
+    pub fn shares_data(&self, other: &FileNode) -> bool {
+        self.data_key() == other.data_key()
+    }
+
+    /// What files sharing their data have in common.
+    pub(crate) fn data_key(&self) -> (u64, usize, usize, FileStorageType) {
+        (self.data_position, self.data_length, self.data_uncompressed_length, self.storage_type)
+    }
+
+    /// Size of the file in bytes as stored in the archive.
+    pub fn stored_size(&self) -> usize {
+        self.data_length
+    }
+
+    /// Size of the file in bytes once decoded.
+    pub fn size(&self) -> usize {
+        self.data_uncompressed_length
+    }
+
+    /// Reads the data from the file and returns it as a vector, decoded with the default codecs.
     pub fn read_data<T: Read + Seek>(&self, reader: &mut T) -> Result<Vec<u8>> {
-        reader.seek(SeekFrom::Start(self.data_position))?;
+        self.read_data_with_codecs(reader, &CodecRegistry::default())
+    }
+
+    /// Reads the data from the file and returns it as a vector, decoded with the codec registered for its storage type.
+    /// Files without a registered codec are returned as the raw stored bytes.
+    pub fn read_data_with_codecs<T: Read + Seek>(&self, reader: &mut T, codecs: &CodecRegistry) -> Result<Vec<u8>> {
+        let expected_length = self.output_size(codecs);
+
+        let mut data = Vec::with_capacity(expected_length);
+        self.open_data(reader, codecs)?.read_to_end(&mut data)?;
+        check_length(data.len(), expected_length)?;
+
+        Ok(data)
+    }
 
-        match self.storage_type {
-            FileStorageType::Store | FileStorageType::Unknown(_) => {
-                let mut data = vec![0u8; self.data_length as usize];
-                reader.read_exact(&mut data)?;
-                Ok(data)
-            },
-            FileStorageType::StreamCompress | FileStorageType::BufferCompress => {
-                reader.seek_relative(2)?;
-                let mut deflate_stream = DeflateDecoder::new(reader);
-                let mut decoded = vec![0u8; self.data_uncompressed_length as usize];
-                deflate_stream.read_exact(&mut decoded)?;
-                Ok(decoded)
-            },
-            FileStorageType::StreamCompressBrotli | FileStorageType::BufferCompressBrotli => {
-                let mut brotli_stream = Decompressor::new(reader, 4096); // 4KB buffer
-                let mut decoded = vec![0u8; self.data_uncompressed_length as usize];
-                brotli_stream.read_exact(&mut decoded)?;
-                Ok(decoded)
-            },
+    /// Size of the data `open_data` yields for this file, the stored size if no codec is registered for it.
+    pub fn output_size(&self, codecs: &CodecRegistry) -> usize {
+        match codecs.contains(self.storage_type) {
+            true => self.data_uncompressed_length,
+            false => self.data_length,
         }
     }
-    
+
+    /// Returns a reader which streams the decoded data of the file.
+    /// Files without a registered codec are streamed as the raw stored bytes.
+    pub fn open_data<'a, T: Read + Seek>(&self, reader: &'a mut T, codecs: &CodecRegistry) -> Result<Box<dyn Read + 'a>> {
+        let stored = self.open_stored(reader)?;
+        let decoded = self.decode(stored, codecs)?;
+
+        Ok(Box::new(ExactLengthReader::new(decoded, self.output_size(codecs) as u64)))
+    }
+
+    /// Returns a reader which streams the data of the file as it is stored in the archive.
+    pub fn open_stored<'a, T: Read + Seek>(&self, reader: &'a mut T) -> Result<Box<dyn Read + 'a>> {
+        reader.seek(SeekFrom::Start(self.data_position))?;
+        Ok(Box::new(reader.take(self.data_length as u64)))
+    }
+
+    /// Reads the data of the file as it is stored in the archive.
+    pub fn read_stored<T: Read + Seek>(&self, reader: &mut T) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(self.data_length);
+        self.open_stored(reader)?.read_to_end(&mut data)?;
+        check_length(data.len(), self.data_length)?;
+
+        Ok(data)
+    }
+
+    /// Decodes the `stored` data of the file with the codec registered for its storage type,
+    /// without checking the length of the result. Files without a registered codec are returned as is.
+    pub fn decode<'a>(&self, stored: Box<dyn Read + 'a>, codecs: &CodecRegistry) -> Result<Box<dyn Read + 'a>> {
+        Ok(match codecs.get(self.storage_type) {
+            Some(codec) => codec.decoder(stored, self.data_uncompressed_length)?,
+            None => stored,
+        })
+    }
+
     /// Constructs a file node from an SgaFileEntry
     /// Files are always expected to have a parent, that is why it is a required field here
     pub fn from_file_entry<T: Read + BufRead + Seek>(reader: &mut T, entries: &SgaEntries, file_entry: SgaFileEntry, parent: Arc<Mutex<FolderNode>>) -> anyhow::Result<Self> {
@@ -78,7 +156,10 @@ impl FileNode {
             data_length: file_entry.compressed_length as usize,
             data_uncompressed_length: file_entry.uncompressed_size as usize,
             storage_type: file_entry.storage_type,
-            parent
+            parent,
+
+            index: None,
+            entry: Some(file_entry),
         })
     }
 }
@@ -1,6 +1,5 @@
 use std::{
     io::{BufRead, Read, Seek, SeekFrom},
-    path::Path,
     sync::{Arc, Mutex},
 };
 
@@ -35,15 +34,12 @@ pub struct FolderNode {
 }
 
 /// Reads the folder's name from a stream, you need to seek to the proper position before usage.
+/// Folder names are stored as full paths separated by `\`, only the last component is kept.
 fn read_folder_name_from_stream<U: Read + BufRead>(reader: &mut U) -> Result<String> {
-    let mut folder_name = read_c_string(reader)?;
+    let folder_path = read_c_string(reader)?;
+    let folder_name = folder_path.rsplit(['\\', '/']).next().unwrap_or_default();
 
-    let parsed = Path::new(&folder_name);
-    if let Some(name) = parsed.file_name() {
-        folder_name = name.to_string_lossy().to_string();
-    }
-
-    Ok(folder_name)
+    Ok(folder_name.to_string())
 }
 
 impl FolderNode {
@@ -54,10 +50,7 @@ impl FolderNode {
         parent: Option<Arc<Mutex<FolderNode>>>,
         children: Option<Vec<Node>>,
     ) -> Self {
-        let children = match children {
-            Some(val) => val,
-            None => Vec::new(),
-        };
+        let children = children.unwrap_or_default();
 
         Self {
             name,
@@ -94,9 +87,10 @@ impl FolderNode {
                 entries.header.data_offset + file_entry.data_offset,
                 file_entry.compressed_length as usize,
                 file_entry.uncompressed_size as usize,
-                file_entry.storage_type.clone(),
+                file_entry.storage_type,
                 this.clone(),
-            );
+            )
+            .with_entry(i as usize, file_entry.clone());
 
             nodes.push(node);
         }
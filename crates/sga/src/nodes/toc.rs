@@ -28,10 +28,28 @@ impl Toc {
         }
     }
 
+    /// Returns true if `alias_or_name` is the alias or the name of this Toc, ignoring case.
+    pub fn matches(&self, alias_or_name: &str) -> bool {
+        self.alias.eq_ignore_ascii_case(alias_or_name) || self.name.eq_ignore_ascii_case(alias_or_name)
+    }
+
+    /// Name of the folder this Toc is extracted into, its alias, or its name if it has no alias.
+    pub fn directory_name(&self) -> &str {
+        match self.alias.is_empty() {
+            true => &self.name,
+            false => &self.alias,
+        }
+    }
+
+    /// Number of files in this Toc.
+    pub fn file_count(&self) -> usize {
+        self.toc_entry.file_end_index.saturating_sub(self.toc_entry.file_start_index) as usize
+    }
+
     /// Initialize a Toc from an entry file
     pub fn initialize_from_entry<T: Read + BufRead + Seek>(reader: &mut T, entries: &SgaEntries, toc: SgaToC) -> Result<Self> {
         let root_folder_entry = &entries.folders[toc.folder_root_index as usize];
-        let root_folder = FolderNode::folder_from_entry(reader, entries, &root_folder_entry, None)?;
+        let root_folder = FolderNode::folder_from_entry(reader, entries, root_folder_entry, None)?;
 
         Ok(Self {
             name: toc.name.clone(),
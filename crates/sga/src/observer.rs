@@ -0,0 +1,83 @@
+use std::{collections::HashSet, path::Path};
+
+use crate::{entires::SgaEntries, plan::ExtractPlan};
+
+/// Summary of an archive, reported when extraction of it starts.
+#[derive(Debug, Clone)]
+pub struct ArchiveSummary {
+    /// Name of the archive from its header.
+    pub name: String,
+
+    /// Archive version.
+    pub version: u16,
+
+    /// Number of tables of contents being extracted.
+    pub toc_count: usize,
+
+    /// Number of files being extracted.
+    pub file_count: usize,
+
+    /// Total size of the files being extracted once decoded, in bytes.
+    pub total_bytes: u64,
+
+    /// Total size of the files being extracted as stored in the archive, in bytes.
+    pub stored_bytes: u64,
+}
+
+impl ArchiveSummary {
+    /// Summarizes the whole archive described by `entries`.
+    pub fn from_entries(entries: &SgaEntries) -> Self {
+        Self {
+            name: entries.header.name.clone(),
+            version: entries.header.version,
+            toc_count: entries.tocs.len(),
+            file_count: entries.files.len(),
+            total_bytes: entries.files.iter().map(|file| file.uncompressed_size as u64).sum(),
+            stored_bytes: entries.files.iter().map(|file| file.compressed_length as u64).sum(),
+        }
+    }
+
+    /// Summarizes the files of the archive described by `entries` which `plan` extracts.
+    pub fn from_plan(entries: &SgaEntries, plan: &ExtractPlan) -> Self {
+        let tocs: HashSet<_> = plan.files.iter().map(|planned| planned.toc.as_str()).collect();
+
+        Self {
+            name: entries.header.name.clone(),
+            version: entries.header.version,
+            toc_count: tocs.len(),
+            file_count: plan.files.len(),
+            total_bytes: plan.total_bytes(),
+            stored_bytes: plan.stored_bytes(),
+        }
+    }
+}
+
+/// Receives events while an archive is extracted, for example to report progress.
+///
+/// Every method has an empty default implementation, so only the events of interest need implementing.
+/// Paths are relative to the output the archive is extracted into.
+pub trait ExtractObserver: Send + Sync {
+    /// Called once the archive has been opened and its entries parsed.
+    fn archive_opened(&self, _archive: &ArchiveSummary) {}
+
+    /// Called before the contents of a folder are extracted.
+    fn folder_entered(&self, _path: &Path) {}
+
+    /// Called before a file is extracted, with its decoded size in bytes.
+    fn file_started(&self, _path: &Path, _size: u64) {}
+
+    /// Called after a file has been extracted, with the number of bytes written.
+    fn file_finished(&self, _path: &Path, _bytes_written: u64) {}
+
+    /// Called when something unexpected happened which does not stop the extraction.
+    fn warning(&self, _path: Option<&Path>, _message: &str) {}
+
+    /// Called when an error stops the extraction, right before it is returned.
+    fn error(&self, _path: Option<&Path>, _error: &anyhow::Error) {}
+}
+
+/// An observer which ignores every event.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopObserver;
+
+impl ExtractObserver for NoopObserver {}
@@ -0,0 +1,390 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock},
+};
+
+use anyhow::{Context, Result};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+use crate::{
+    archive::TocLayout,
+    cancel::{CancellationToken, Cancelled},
+    codec::CodecRegistry,
+    manifest::ManifestFormat,
+    observer::{ExtractObserver, NoopObserver},
+};
+
+/// What happens when a file to extract already exists in the output,
+/// or has the same path as an earlier file of the extraction when case is ignored.
+/// Conflicts are all found before anything is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverwritePolicy {
+    /// The other file is replaced.
+    #[default]
+    Overwrite,
+
+    /// The other file is kept and the file is not extracted.
+    Skip,
+
+    /// The file is extracted next to the other one, with a ` (n)` suffix added to its name.
+    Rename,
+
+    /// The extraction stops with an error listing every conflict, before writing anything.
+    Error,
+}
+
+/// How thoroughly the data of each file is checked while it's extracted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VerificationLevel {
+    /// The decoded data is written as is, only the codecs' own checks are done.
+    None,
+
+    /// The decoded data must have the size recorded in the archive.
+    #[default]
+    Length,
+
+    /// Like `Length`, and the stored data of files with the CRC verification type must match their CRC32 checksum.
+    Crc,
+}
+
+/// How `SgaArchive::extract` protects its output folder from being seen partially written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AtomicMode {
+    /// Files are written in place.
+    #[default]
+    None,
+
+    /// Every file is written into a temporary file, which is renamed over its path once complete.
+    /// Other files in the output folder are left alone, which suits updating an existing extraction.
+    PerFile,
+
+    /// Everything is extracted into a hidden folder next to the output folder, which is verified
+    /// and then replaces the output folder. Nothing else in the previous output folder is kept,
+    /// and the output folder is left untouched if the extraction fails.
+    ///
+    /// The previous output folder is renamed aside before the hidden folder is renamed into its place,
+    /// so for a moment between the two renames the output folder doesn't exist.
+    Staged,
+}
+
+/// How files sharing their stored data with an earlier file of the same extraction are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LinkMode {
+    /// Every file is decoded and written on its own.
+    #[default]
+    None,
+
+    /// The shared data is decoded once, and the later files are hard links to the first one.
+    /// Changing one of the extracted files then changes all of them.
+    Hardlink,
+
+    /// The shared data is decoded once, and the later files are clones of the first one sharing its blocks on disk,
+    /// on file systems supporting it like btrfs, XFS or APFS. Other file systems get plain copies of the first file.
+    Reflink,
+}
+
+/// Settings of an extraction, built by chaining its methods.
+///
+/// ```no_run
+/// use sga::{archive::SgaArchive, ExtractOptions};
+///
+/// let options = ExtractOptions::new("./ArtJapanese")
+///     .toc("data")
+///     .include("art/**/*.dds")
+///     .jobs(4);
+/// SgaArchive::open("./ArtJapanese.sga")?.extract(&options)?;
+/// # anyhow::Ok(())
+/// ```
+#[derive(Clone)]
+pub struct ExtractOptions {
+    pub(crate) output: PathBuf,
+    pub(crate) tocs: Vec<String>,
+    pub(crate) toc_layout: TocLayout,
+    pub(crate) subtree: Option<String>,
+    pub(crate) max_depth: Option<usize>,
+    pub(crate) flatten: bool,
+    pub(crate) include: Vec<String>,
+    pub(crate) exclude: Vec<String>,
+    pub(crate) overwrite: OverwritePolicy,
+    pub(crate) verification: VerificationLevel,
+    pub(crate) atomic: AtomicMode,
+    pub(crate) resume: bool,
+    pub(crate) manifest: Option<ManifestFormat>,
+    pub(crate) raw: bool,
+    pub(crate) link: LinkMode,
+    pub(crate) jobs: usize,
+    pub(crate) dry_run: bool,
+    pub(crate) observer: Arc<dyn ExtractObserver>,
+    pub(crate) codecs: CodecRegistry,
+    pub(crate) cancellation: Option<CancellationToken>,
+    pub(crate) remove_partial_files: bool,
+}
+
+impl ExtractOptions {
+    /// Creates the default options, extracting everything into the folder at `output`.
+    pub fn new<P: AsRef<Path>>(output: P) -> Self {
+        Self {
+            output: output.as_ref().to_path_buf(),
+            tocs: Vec::new(),
+            toc_layout: TocLayout::default(),
+            subtree: None,
+            max_depth: None,
+            flatten: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            overwrite: OverwritePolicy::default(),
+            verification: VerificationLevel::default(),
+            atomic: AtomicMode::default(),
+            resume: false,
+            manifest: None,
+            raw: false,
+            link: LinkMode::default(),
+            jobs: 1,
+            dry_run: false,
+            observer: Arc::new(NoopObserver),
+            codecs: CodecRegistry::default(),
+            cancellation: None,
+            remove_partial_files: false,
+        }
+    }
+
+    /// The folder `SgaArchive::extract` writes into.
+    pub fn output_path(&self) -> &Path {
+        &self.output
+    }
+
+    /// Sets the folder `SgaArchive::extract` writes into.
+    pub fn output<P: AsRef<Path>>(mut self, output: P) -> Self {
+        self.output = output.as_ref().to_path_buf();
+        self
+    }
+
+    /// Only extracts the table of contents with this alias or name, can be called multiple times.
+    /// Every table of contents is extracted if none is selected.
+    pub fn toc<S: Into<String>>(mut self, alias_or_name: S) -> Self {
+        self.tocs.push(alias_or_name.into());
+        self
+    }
+
+    /// Only extracts the tables of contents with these aliases or names, replacing the previous selection.
+    pub fn tocs<I: IntoIterator<Item = S>, S: Into<String>>(mut self, aliases_or_names: I) -> Self {
+        self.tocs = aliases_or_names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets where each table of contents is extracted to.
+    pub fn toc_layout(mut self, toc_layout: TocLayout) -> Self {
+        self.toc_layout = toc_layout;
+        self
+    }
+
+    /// Only extracts the folder at `path` inside each table of contents, for example `art/ui`.
+    pub fn subtree<S: Into<String>>(mut self, path: S) -> Self {
+        self.subtree = Some(path.into());
+        self
+    }
+
+    /// Sets how many levels of folders below the extracted folder are descended into.
+    /// `0` only extracts the files directly inside it.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Writes every extracted file directly into the output folder of its table of contents, without the folders.
+    pub fn flatten(mut self, flatten: bool) -> Self {
+        self.flatten = flatten;
+        self
+    }
+
+    /// Only extracts files whose path inside their table of contents matches the glob `pattern`, ignoring case.
+    /// Can be called multiple times, a file is extracted if it matches any of them.
+    pub fn include<S: Into<String>>(mut self, pattern: S) -> Self {
+        self.include.push(pattern.into());
+        self
+    }
+
+    /// Skips files whose path inside their table of contents matches the glob `pattern`, ignoring case.
+    /// Exclusions take precedence over inclusions.
+    pub fn exclude<S: Into<String>>(mut self, pattern: S) -> Self {
+        self.exclude.push(pattern.into());
+        self
+    }
+
+    /// Sets what happens when a file already exists in the output, or collides with another extracted file.
+    pub fn overwrite(mut self, policy: OverwritePolicy) -> Self {
+        self.overwrite = policy;
+        self
+    }
+
+    /// Sets how thoroughly the extracted data is checked.
+    pub fn verification(mut self, level: VerificationLevel) -> Self {
+        self.verification = level;
+        self
+    }
+
+    /// Sets how `SgaArchive::extract` protects the output folder from being seen partially written.
+    pub fn atomic(mut self, mode: AtomicMode) -> Self {
+        self.atomic = mode;
+        self
+    }
+
+    /// Continues an interrupted `SgaArchive::extract` into the same output folder, leaving out the files
+    /// its journal records as completed. Everything is extracted if there is no journal for this archive.
+    pub fn resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// Writes a manifest in `format` into the root of the output once the files are extracted,
+    /// recording the archive metadata of every extracted file and the header of the archive.
+    pub fn manifest(mut self, format: ManifestFormat) -> Self {
+        self.manifest = Some(format);
+        self
+    }
+
+    /// Writes the data of every file exactly as it's stored in the archive, without decoding it.
+    /// The manifest of a raw extraction also records the layout of the archive, which `pack::repack_raw` rebuilds it from byte for byte.
+    pub fn raw(mut self, raw: bool) -> Self {
+        self.raw = raw;
+        self
+    }
+
+    /// Sets how files sharing their stored data with an earlier file are written. Sinks which can't link files,
+    /// like tar and zip files, write them as usual.
+    pub fn link_shared(mut self, mode: LinkMode) -> Self {
+        self.link = mode;
+        self
+    }
+
+    /// Sets how many threads decode files, `1` decodes them on the calling thread while streaming them to the output.
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    /// Only plans the extraction, without reading any file data or writing anything.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Sets the observer receiving progress events.
+    pub fn observer(mut self, observer: Arc<dyn ExtractObserver>) -> Self {
+        self.observer = observer;
+        self
+    }
+
+    /// Sets the codecs used to decode file data.
+    pub fn codecs(mut self, codecs: CodecRegistry) -> Self {
+        self.codecs = codecs;
+        self
+    }
+
+    /// Stops the extraction with a `Cancelled` error once `token` is cancelled.
+    /// It is checked between files and between chunks of a file.
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Removes the file being written when extraction stops because of an error or cancellation.
+    pub fn remove_partial_files(mut self, remove_partial_files: bool) -> Self {
+        self.remove_partial_files = remove_partial_files;
+        self
+    }
+
+    /// Returns `Err(Cancelled)` if the extraction has been cancelled.
+    pub(crate) fn check_cancelled(&self) -> Result<(), Cancelled> {
+        match &self.cancellation {
+            Some(token) => token.check(),
+            None => Ok(()),
+        }
+    }
+
+    /// The codecs files are decoded with, none for a raw extraction.
+    pub(crate) fn decoding_codecs(&self) -> &CodecRegistry {
+        static NO_CODECS: LazyLock<CodecRegistry> = LazyLock::new(CodecRegistry::empty);
+
+        match self.raw {
+            true => &NO_CODECS,
+            false => &self.codecs,
+        }
+    }
+
+    /// Compiles the include and exclude patterns.
+    pub(crate) fn file_filter(&self) -> Result<FileFilter> {
+        Ok(FileFilter {
+            include: match self.include.is_empty() {
+                true => None,
+                false => Some(build_glob_set(&self.include)?),
+            },
+            exclude: build_glob_set(&self.exclude)?,
+        })
+    }
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        Self::new(".")
+    }
+}
+
+impl std::fmt::Debug for ExtractOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExtractOptions")
+            .field("output", &self.output)
+            .field("tocs", &self.tocs)
+            .field("toc_layout", &self.toc_layout)
+            .field("subtree", &self.subtree)
+            .field("max_depth", &self.max_depth)
+            .field("flatten", &self.flatten)
+            .field("include", &self.include)
+            .field("exclude", &self.exclude)
+            .field("overwrite", &self.overwrite)
+            .field("verification", &self.verification)
+            .field("atomic", &self.atomic)
+            .field("resume", &self.resume)
+            .field("manifest", &self.manifest)
+            .field("raw", &self.raw)
+            .field("link", &self.link)
+            .field("jobs", &self.jobs)
+            .field("dry_run", &self.dry_run)
+            .field("codecs", &self.codecs)
+            .field("cancellation", &self.cancellation)
+            .field("remove_partial_files", &self.remove_partial_files)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The compiled include and exclude patterns of `ExtractOptions`.
+#[derive(Debug, Clone)]
+pub(crate) struct FileFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl FileFilter {
+    /// Whether any pattern was given, in which case folders without selected files are left out.
+    pub(crate) fn is_active(&self) -> bool {
+        self.include.is_some() || !self.exclude.is_empty()
+    }
+
+    /// Whether the file at `archive_path`, separated by `/`, is extracted.
+    pub(crate) fn matches(&self, archive_path: &str) -> bool {
+        self.include.as_ref().is_none_or(|include| include.is_match(archive_path)) && !self.exclude.is_match(archive_path)
+    }
+}
+
+pub(crate) fn build_glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .with_context(|| format!("Invalid pattern '{}'", pattern))?;
+        builder.add(glob);
+    }
+
+    Ok(builder.build()?)
+}
@@ -0,0 +1,238 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fs::{self, File},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use anyhow::{bail, Context, Result};
+use md5::Md5;
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+use crate::{
+    entires::{FileVerificationType, SgaFileEntry, SgaHeader},
+    layout::ArchiveLayout,
+    manifest::{Manifest, ManifestFormat},
+};
+
+/// A part of the archive file, at an absolute offset.
+enum Region {
+    Header,
+    File(usize),
+    HeaderBlob,
+}
+
+/// Writes the archive described by `layout`.
+///
+/// The stored data of every file entry is asked from `stored_data` with the index of the entry, in the order it's written.
+/// Entries sharing the same data are asked once. Whatever isn't covered by the header, the data of a file or the header blob
+/// is filled with zeros.
+pub fn write_archive<W, F>(writer: &mut W, layout: &ArchiveLayout, mut stored_data: F) -> Result<()>
+where
+    W: Write,
+    F: FnMut(usize, &SgaFileEntry) -> Result<Vec<u8>>,
+{
+    let header = &layout.header;
+    let header_blob = layout.header_blob()?;
+
+    let mut regions = vec![(0, SgaHeader::SIZE, Region::Header)];
+    regions.push((header.header_blob_offset, header_blob.len() as u64, Region::HeaderBlob));
+    for (index, entry) in layout.files.iter().enumerate() {
+        let end = entry.data_offset + entry.compressed_length as u64;
+        if end > header.data_blob_length {
+            bail!("The data of file entry {} ends at {}, past the end of the data blob at {}", index, end, header.data_blob_length);
+        }
+        regions.push((header.data_offset + entry.data_offset, entry.compressed_length as u64, Region::File(index)));
+    }
+    regions.retain(|(_, length, _)| *length > 0);
+    regions.sort_by_key(|(offset, length, _)| (*offset, *length));
+
+    let mut position = 0;
+    let mut previous = None;
+    for (offset, length, region) in regions {
+        if offset < position {
+            match (&region, previous) {
+                (Region::File(_), Some(shared)) if shared == (offset, length) => continue,
+                _ => bail!("{} at offset {} overlaps what comes before it", describe(&region), offset),
+            }
+        }
+
+        write_zeros(writer, offset - position)?;
+        match region {
+            Region::Header => header.write(writer)?,
+            Region::HeaderBlob => writer.write_all(&header_blob)?,
+            Region::File(index) => {
+                let entry = &layout.files[index];
+                let data = stored_data(index, entry)?;
+                if data.len() as u64 != length {
+                    bail!("File entry {} has {} bytes of stored data, but its entry records {}", index, data.len(), length);
+                }
+                writer.write_all(&data)?;
+            }
+        }
+
+        position = offset + length;
+        previous = Some((offset, length));
+    }
+
+    let end = (header.data_offset + header.data_blob_length).max(position);
+    write_zeros(writer, end - position)?;
+
+    Ok(())
+}
+
+/// Writes a new archive whose files are added one by one.
+///
+/// The data blob starts right after the header and the stored data of every file is appended to it as it's added.
+/// Data identical to data added before isn't written again, the file entries share it instead.
+/// Once every file is added, `finish` writes the header blob after the data blob and then the header.
+#[derive(Debug)]
+pub struct ArchiveWriter<W: Write + Seek> {
+    writer: W,
+    data_blob_length: u64,
+    /// The offset of the data added so far by its length and SHA-256, if identical data is shared.
+    /// SHA-1 collisions can be made on purpose, which would make two different files share their data.
+    added: Option<HashMap<(u64, [u8; 32]), u64>>,
+}
+
+impl<W: Write + Seek> ArchiveWriter<W> {
+    /// Starts writing an archive at the start of `writer`.
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.seek(SeekFrom::Start(0))?;
+        write_zeros(&mut writer, SgaHeader::SIZE)?;
+        Ok(Self { writer, data_blob_length: 0, added: Some(HashMap::new()) })
+    }
+
+    /// Sets whether data identical to data added before is shared instead of written again, which it is by default.
+    pub fn deduplicate(mut self, deduplicate: bool) -> Self {
+        self.added = match deduplicate {
+            true => Some(self.added.unwrap_or_default()),
+            false => None,
+        };
+        self
+    }
+
+    /// Appends the `stored` data of a file to the data blob, and returns its offset in the data blob.
+    /// If the same data was added before, the offset of that data is returned instead.
+    pub fn add_data(&mut self, stored: &[u8]) -> io::Result<u64> {
+        let offset = self.data_blob_length;
+        if let Some(added) = &mut self.added {
+            match added.entry((stored.len() as u64, Sha256::digest(stored).into())) {
+                Entry::Occupied(shared) => return Ok(*shared.get()),
+                Entry::Vacant(vacant) => {
+                    vacant.insert(offset);
+                }
+            }
+        }
+
+        self.writer.write_all(stored)?;
+        self.data_blob_length += stored.len() as u64;
+        Ok(offset)
+    }
+
+    /// Writes the header blob and the header of `layout`, whose file entries point at the added data.
+    /// The offsets and lengths of the header are set to where everything was written. Returns the writer and the final layout.
+    pub fn finish(mut self, mut layout: ArchiveLayout) -> Result<(W, ArchiveLayout)> {
+        layout.arrange_header_blob()?;
+        let header = &mut layout.header;
+        header.data_offset = SgaHeader::SIZE;
+        header.data_blob_length = self.data_blob_length;
+        header.header_blob_offset = SgaHeader::SIZE + self.data_blob_length;
+
+        self.writer.write_all(&layout.header_blob()?)?;
+        self.writer.seek(SeekFrom::Start(0))?;
+        layout.header.write(&mut self.writer)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+
+        Ok((self.writer, layout))
+    }
+}
+
+/// The hashes `verification_type` records in the hash blob for the `stored` data of a file:
+/// a CRC32, MD5 or SHA1 for every `block_size` bytes. Empty for the verification types without block hashes.
+pub fn block_hashes(verification_type: FileVerificationType, stored: &[u8], block_size: u32) -> Vec<u8> {
+    let blocks = stored.chunks(block_size.max(1) as usize);
+    match verification_type {
+        FileVerificationType::None | FileVerificationType::CRC => Vec::new(),
+        FileVerificationType::CRCBlocks => blocks.flat_map(|block| crc32fast::hash(block).to_le_bytes()).collect(),
+        FileVerificationType::MD5Blocks => blocks.flat_map(Md5::digest).collect(),
+        FileVerificationType::SHA1Blocks => blocks.flat_map(Sha1::digest).collect(),
+    }
+}
+
+/// The number of bytes `block_hashes` gives for `stored_length` bytes of stored data.
+pub fn block_hash_length(verification_type: FileVerificationType, stored_length: u64, block_size: u32) -> u64 {
+    let blocks = stored_length.div_ceil(block_size.max(1) as u64);
+    match verification_type {
+        FileVerificationType::None | FileVerificationType::CRC => 0,
+        FileVerificationType::CRCBlocks => blocks * 4,
+        FileVerificationType::MD5Blocks => blocks * 16,
+        FileVerificationType::SHA1Blocks => blocks * 20,
+    }
+}
+
+/// Rebuilds the archive extracted into `folder` with `ExtractOptions::raw` and a manifest, byte for byte, and writes it to `output`.
+pub fn repack_raw<P: AsRef<Path>, Q: AsRef<Path>>(folder: P, output: Q) -> Result<()> {
+    let folder = folder.as_ref();
+    let manifest = read_folder_manifest(folder)?;
+    let Some(layout) = &manifest.layout else {
+        bail!("The manifest in '{}' has no layout, the archive must be extracted raw to be repacked", folder.display());
+    };
+
+    let paths: HashMap<usize, &str> = manifest
+        .files
+        .iter()
+        .filter_map(|file| Some((file.index?, file.path.as_str())))
+        .collect();
+    if let Some(missing) = (0..layout.files.len()).find(|index| !paths.contains_key(index)) {
+        bail!("File entry {} was not extracted into '{}', every file is needed to repack the archive", missing, folder.display());
+    }
+
+    let output = output.as_ref();
+    let mut writer = BufWriter::new(File::create(output).with_context(|| format!("Failed to create '{}'", output.display()))?);
+    write_archive(&mut writer, layout, |index, _| {
+        let path = paths[&index];
+        fs::read(folder.join(path)).with_context(|| format!("Failed to read '{}'", path))
+    })?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Reads the manifest written into the root of `folder` by an extraction, in either format.
+fn read_folder_manifest(folder: &Path) -> Result<Manifest> {
+    match folder_manifest(folder)? {
+        Some(manifest) => Ok(manifest),
+        None => bail!("There is no manifest in '{}'", folder.display()),
+    }
+}
+
+/// Reads the manifest written into the root of `folder` by an extraction, in either format, if there is one.
+pub(crate) fn folder_manifest(folder: &Path) -> Result<Option<Manifest>> {
+    for format in [ManifestFormat::Json, ManifestFormat::Toml] {
+        let path = folder.join(format.file_name());
+        if path.exists() {
+            return Manifest::read(&path)
+                .map(Some)
+                .with_context(|| format!("Failed to read the manifest '{}'", path.display()));
+        }
+    }
+
+    Ok(None)
+}
+
+fn describe(region: &Region) -> String {
+    match region {
+        Region::Header => "The header".to_string(),
+        Region::File(index) => format!("The data of file entry {}", index),
+        Region::HeaderBlob => "The header blob".to_string(),
+    }
+}
+
+/// Writes `length` zeros.
+fn write_zeros<W: Write>(writer: &mut W, length: u64) -> io::Result<()> {
+    io::copy(&mut io::repeat(0).take(length), writer)?;
+    Ok(())
+}
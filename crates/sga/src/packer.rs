@@ -0,0 +1,522 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::{BufRead, Read, Seek, Write},
+    path::Path,
+    sync::{mpsc, Mutex},
+    thread,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    archive::SgaArchive,
+    builder::{ArchiveBuilder, StoredFile},
+    codec::CodecRegistry,
+    entires::{FileStorageType, FileVerificationType},
+    journal::JOURNAL_FILE_NAME,
+    layout::ArchiveLayout,
+    manifest::ManifestFormat,
+    options::{build_glob_set, ExtractOptions},
+    pack::folder_manifest,
+};
+
+/// How the files matching a pattern are packed. Settings left out come from the next rules matching the file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackRule {
+    /// Glob pattern matched against the path of the file inside its table of contents, ignoring case, like `art/**` or `*.lua`.
+    pub pattern: String,
+
+    /// Only applies the rule to files smaller than this many bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smaller_than: Option<u64>,
+
+    /// Storage type of the matching files, which codec their data is encoded with if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage_type: Option<FileStorageType>,
+
+    /// Codec specific compression level.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<u32>,
+
+    /// Verification type of the matching files, whether their entry holds the CRC of their data
+    /// or the hash blob holds hashes of its blocks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verification_type: Option<FileVerificationType>,
+}
+
+impl PackRule {
+    /// Creates a rule for the files matching `pattern`, which doesn't set anything yet.
+    pub fn new<S: Into<String>>(pattern: S) -> Self {
+        Self { pattern: pattern.into(), smaller_than: None, storage_type: None, level: None, verification_type: None }
+    }
+
+    /// Only applies the rule to files smaller than `size` bytes.
+    pub fn smaller_than(mut self, size: u64) -> Self {
+        self.smaller_than = Some(size);
+        self
+    }
+
+    /// Stores the matching files as `storage_type`.
+    pub fn storage_type(mut self, storage_type: FileStorageType) -> Self {
+        self.storage_type = Some(storage_type);
+        self
+    }
+
+    /// Encodes the matching files with the codec specific compression `level`.
+    pub fn level(mut self, level: u32) -> Self {
+        self.level = Some(level);
+        self
+    }
+
+    /// Verifies the matching files with `verification_type`.
+    pub fn verification_type(mut self, verification_type: FileVerificationType) -> Self {
+        self.verification_type = Some(verification_type);
+        self
+    }
+}
+
+/// How `pack_folder` stores each file, which can be read from a JSON or TOML file.
+///
+/// The rules are tried in order, and each setting of a file comes from the first rule matching it which sets it.
+/// Settings no rule sets come from the manifest of the packed folder if it describes the file, and from the defaults otherwise.
+///
+/// ```toml
+/// storage_type = "BufferCompress"
+///
+/// [[rules]]
+/// pattern = "*.lua"
+/// storage_type = "Store"
+///
+/// [[rules]]
+/// pattern = "art/**"
+/// storage_type = "BufferCompressBrotli"
+/// level = 9
+/// verification_type = "SHA1Blocks"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PackConfig {
+    /// Storage type of the files no rule sets one for.
+    pub storage_type: FileStorageType,
+
+    /// Codec specific compression level of the files no rule sets one for, `None` uses each codec's default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<u32>,
+
+    /// Verification type of the files no rule sets one for.
+    pub verification_type: FileVerificationType,
+
+    /// Rules setting how the files matching their pattern are stored, tried in order.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<PackRule>,
+}
+
+impl Default for PackConfig {
+    /// Stores every file uncompressed and verified by its CRC.
+    fn default() -> Self {
+        Self { storage_type: FileStorageType::Store, level: None, verification_type: FileVerificationType::CRC, rules: Vec::new() }
+    }
+}
+
+impl PackConfig {
+    /// Parses a config written in `format`.
+    pub fn parse(text: &str, format: ManifestFormat) -> Result<Self> {
+        Ok(match format {
+            ManifestFormat::Json => serde_json::from_str(text)?,
+            ManifestFormat::Toml => toml::from_str(text)?,
+        })
+    }
+
+    /// Reads the config at `path`, in the format given by its extension.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let Some(format) = ManifestFormat::from_path(path) else {
+            bail!("Unknown config format for '{}', expected a .json or .toml file", path.display());
+        };
+        let text = fs::read_to_string(path).with_context(|| format!("Failed to read '{}'", path.display()))?;
+        Self::parse(&text, format).with_context(|| format!("Invalid config '{}'", path.display()))
+    }
+
+    /// The config packing files like `archive` stores them, to pack files like the official archives of a game.
+    ///
+    /// The storage and verification type most files of the archive have become the defaults, and every file extension
+    /// whose files mostly have others gets a rule `*.{extension}` setting both. Of settings as common as each other,
+    /// the first one in the archive wins. Archives don't record compression levels, so each codec's default is used,
+    /// and files of unknown storage types are left out.
+    pub fn from_archive<R: Read + BufRead + Seek>(archive: &SgaArchive<R>) -> Result<Self> {
+        let plan = archive.plan(&ExtractOptions::default())?;
+        let mut settings = Vec::new();
+        let mut by_extension: BTreeMap<String, Vec<_>> = BTreeMap::new();
+        for planned in &plan.files {
+            let Some(entry) = planned.file.entry() else {
+                continue;
+            };
+            if matches!(entry.storage_type, FileStorageType::Unknown(_)) {
+                continue;
+            }
+
+            let setting = (entry.storage_type, entry.verification_type);
+            settings.push(setting);
+            // Extensions which aren't plain words would need escaping in a glob pattern.
+            let extension = Path::new(&planned.archive_path).extension().map(|extension| extension.to_string_lossy().to_lowercase());
+            if let Some(extension) = extension.filter(|extension| extension.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-')) {
+                by_extension.entry(extension).or_default().push(setting);
+            }
+        }
+
+        let Some(default) = most_common(&settings) else {
+            return Ok(Self::default());
+        };
+        let rules = by_extension
+            .into_iter()
+            .filter_map(|(extension, settings)| {
+                let (storage_type, verification_type) = most_common(&settings).filter(|setting| *setting != default)?;
+                Some(PackRule::new(format!("*.{}", extension)).storage_type(storage_type).verification_type(verification_type))
+            })
+            .collect();
+
+        Ok(Self { storage_type: default.0, level: None, verification_type: default.1, rules })
+    }
+}
+
+/// The value occurring most often in `values`, the first one to occur of those occurring as often.
+fn most_common<T: Copy + PartialEq>(values: &[T]) -> Option<T> {
+    let mut counts: Vec<(T, usize)> = Vec::new();
+    for value in values {
+        match counts.iter_mut().find(|(counted, _)| counted == value) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((*value, 1)),
+        }
+    }
+
+    let mut most = None;
+    for (value, count) in counts {
+        if most.is_none_or(|(_, most_count)| count > most_count) {
+            most = Some((value, count));
+        }
+    }
+    most.map(|(value, _)| value)
+}
+
+/// Settings of `pack_folder`, built by chaining its methods.
+///
+/// ```no_run
+/// use std::{fs::File, io::BufWriter};
+/// use sga::{entires::FileStorageType, packer::{pack_folder, PackOptions, PackRule}};
+///
+/// let options = PackOptions::new("attrib")
+///     .storage_type(FileStorageType::BufferCompress)
+///     .rule(PackRule::new("*.lua").storage_type(FileStorageType::Store));
+/// pack_folder("./attrib", BufWriter::new(File::create("./Attrib.sga")?), &options)?;
+/// # anyhow::Ok(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct PackOptions {
+    pub(crate) name: String,
+    pub(crate) config: PackConfig,
+    pub(crate) codecs: CodecRegistry,
+    pub(crate) deduplicate: bool,
+    pub(crate) jobs: usize,
+    pub(crate) buffer_size: u64,
+}
+
+impl PackOptions {
+    /// Creates the default options, packing an archive named `name` with every file stored uncompressed and verified by its CRC.
+    /// The manifest of the packed folder takes precedence over the name.
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Self {
+            name: name.into(),
+            config: PackConfig::default(),
+            codecs: CodecRegistry::default(),
+            deduplicate: true,
+            jobs: 1,
+            buffer_size: 256 * 1024 * 1024,
+        }
+    }
+
+    /// Sets how each file is stored, replacing the storage type, level, verification type and rules set before.
+    pub fn config(mut self, config: PackConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Adds a rule after the rules of the config.
+    pub fn rule(mut self, rule: PackRule) -> Self {
+        self.config.rules.push(rule);
+        self
+    }
+
+    /// Sets the storage type of the files no rule or manifest sets one for.
+    pub fn storage_type(mut self, storage_type: FileStorageType) -> Self {
+        self.config.storage_type = storage_type;
+        self
+    }
+
+    /// Sets the verification type of the files no rule or manifest sets one for.
+    pub fn verification_type(mut self, verification_type: FileVerificationType) -> Self {
+        self.config.verification_type = verification_type;
+        self
+    }
+
+    /// Sets the codec specific compression level of the files no rule sets one for, `None` uses each codec's default.
+    pub fn level(mut self, level: Option<u32>) -> Self {
+        self.config.level = level;
+        self
+    }
+
+    /// Sets the codecs used to encode file data.
+    pub fn codecs(mut self, codecs: CodecRegistry) -> Self {
+        self.codecs = codecs;
+        self
+    }
+
+    /// Sets whether files with identical stored data share it, which they do by default.
+    pub fn deduplicate(mut self, deduplicate: bool) -> Self {
+        self.deduplicate = deduplicate;
+        self
+    }
+
+    /// Sets how many threads encode files, `1` encodes them on the calling thread as they're written.
+    /// The archive is the same whatever the number of threads.
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    /// Sets how many bytes of files, counted by their size on disk, can be read and encoded ahead of the file being written
+    /// when several threads encode files. A file larger than this is only read once every file before it is written.
+    /// 256 MiB by default.
+    pub fn buffer_size(mut self, bytes: u64) -> Self {
+        self.buffer_size = bytes;
+        self
+    }
+}
+
+/// What `pack_folder` wrote.
+#[derive(Debug, Clone, Default)]
+pub struct PackReport {
+    /// Number of tables of contents in the archive.
+    pub tocs: usize,
+
+    /// Number of files in the archive.
+    pub files: usize,
+
+    /// Size of the packed files, in bytes.
+    pub total_bytes: u64,
+
+    /// Size of the data blob of the archive.
+    pub stored_bytes: u64,
+
+    /// Size of the stored data several files share, which is only stored once.
+    pub shared_bytes: u64,
+}
+
+/// A file `pack_folder` packs.
+#[derive(Debug, Clone)]
+struct PackedFile {
+    /// Path of the file relative to the packed folder, separated by `/`.
+    path: String,
+    size: u64,
+    storage_type: FileStorageType,
+    level: Option<u32>,
+    verification_type: FileVerificationType,
+}
+
+/// Packs the files of `folder` into a new archive written into `writer`.
+///
+/// Every folder directly inside `folder` is a table of contents named after it, holding the files below it at their paths,
+/// the way `SgaArchive::extract` lays out tables of contents by default. If `folder` holds the manifest of an extraction,
+/// the version, product, name and block size of the archive come from it, its tables of contents are named and ordered
+/// like it says, and the files it describes are packed into their table of contents with their storage and verification type,
+/// unless a rule of the config of `options` sets another one.
+///
+/// The output only depends on the paths and contents of the files: they're sorted by path, the folders, strings
+/// and data are laid out in a fixed order, the signature is zeros, and no timestamps are recorded.
+/// Packing the same files always gives the same archive, byte for byte.
+pub fn pack_folder<P: AsRef<Path>, W: Write + Seek>(folder: P, writer: W, options: &PackOptions) -> Result<PackReport> {
+    let folder = folder.as_ref();
+    let manifest = folder_manifest(folder)?;
+    let config = &options.config;
+    let patterns: Vec<String> = config.rules.iter().map(|rule| rule.pattern.clone()).collect();
+    let patterns = build_glob_set(&patterns)?;
+
+    let mut builder = ArchiveBuilder::new(options.name.clone());
+    builder.deduplicate = options.deduplicate;
+    let mut described = HashMap::new();
+    if let Some(manifest) = &manifest {
+        if manifest.layout.is_some() {
+            bail!("'{}' was extracted raw, its files are stored data which can only be repacked byte for byte", folder.display());
+        }
+
+        builder.version = manifest.version;
+        builder.product = manifest.product;
+        builder.name = manifest.name.clone();
+        builder.block_size = manifest.block_size;
+        for toc in &manifest.tocs {
+            builder.add_toc(&toc.alias, &toc.name);
+        }
+        described.extend(manifest.files.iter().map(|file| (file.path.as_str(), file)));
+    }
+
+    let mut paths = Vec::new();
+    list_files(folder, "", &mut paths)?;
+    paths.sort();
+
+    let mut total_bytes = 0;
+    for (path, size) in paths {
+        if [ManifestFormat::Json.file_name(), ManifestFormat::Toml.file_name(), JOURNAL_FILE_NAME].contains(&path.as_str()) {
+            continue;
+        }
+
+        let (toc, archive_path, manifest_file) = match described.get(path.as_str()) {
+            Some(file) => (file.toc.clone(), file.archive_path.clone(), Some(*file)),
+            None => {
+                let Some((toc, archive_path)) = path.split_once('/') else {
+                    bail!("'{}' is not inside the folder of a table of contents", path);
+                };
+                (toc.to_string(), archive_path.to_string(), None)
+            }
+        };
+
+        // Each setting comes from the first matching rule setting it.
+        let rules: Vec<&PackRule> = patterns
+            .matches(&archive_path)
+            .into_iter()
+            .map(|index| &config.rules[index])
+            .filter(|rule| rule.smaller_than.is_none_or(|limit| size < limit))
+            .collect();
+        let storage_type = rules.iter().find_map(|rule| rule.storage_type);
+        let level = rules.iter().find_map(|rule| rule.level);
+        let verification_type = rules.iter().find_map(|rule| rule.verification_type);
+
+        let file = PackedFile {
+            storage_type: storage_type.or(manifest_file.map(|file| file.storage_type)).unwrap_or(config.storage_type),
+            level: level.or(config.level),
+            verification_type: verification_type
+                .or(manifest_file.map(|file| file.verification_type))
+                .unwrap_or(config.verification_type),
+            path,
+            size,
+        };
+
+        total_bytes += size;
+        builder.add_file(&toc, archive_path, file);
+    }
+
+    let layout = match options.jobs {
+        1 => builder.write(writer, |file| encode_file(folder, file, options))?,
+        jobs => write_parallel(&builder, folder, writer, options, jobs)?,
+    };
+
+    Ok(PackReport {
+        tocs: layout.tocs.len(),
+        files: layout.files.len(),
+        total_bytes,
+        stored_bytes: layout.header.data_blob_length,
+        shared_bytes: layout.shared_bytes(),
+    })
+}
+
+/// Encodes the files of `builder` on `jobs` threads and writes them on the calling thread, in the order they're laid out.
+/// Files are read ahead of the file being written while the files in flight are smaller than the buffer size together,
+/// and at most two per thread.
+fn write_parallel<W: Write + Seek>(
+    builder: &ArchiveBuilder<PackedFile>,
+    folder: &Path,
+    writer: W,
+    options: &PackOptions,
+    jobs: usize,
+) -> Result<ArchiveLayout> {
+    let files = builder.files_in_layout_order();
+    let max_in_flight = jobs * 2;
+    let (job_sender, job_receiver) = mpsc::channel::<usize>();
+    let (done_sender, done_receiver) = mpsc::channel::<(usize, Result<StoredFile>)>();
+    let job_receiver = Mutex::new(job_receiver);
+
+    thread::scope(|scope| {
+        // Dropped when the writing below returns, which stops the threads before the scope waits for them.
+        let job_sender = job_sender;
+
+        for _ in 0..jobs {
+            let job_receiver = &job_receiver;
+            let done_sender = done_sender.clone();
+            let files = &files;
+
+            scope.spawn(move || {
+                loop {
+                    let job = job_receiver.lock().unwrap().recv();
+                    let Ok(index) = job else {
+                        break;
+                    };
+
+                    let stored = encode_file(folder, files[index], options);
+                    if done_sender.send((index, stored)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(done_sender);
+
+        let mut next = 0;
+        let mut written = 0;
+        let mut buffered = 0;
+        // The encoded files which came back before the files laid out before them.
+        let mut done: HashMap<usize, Result<StoredFile>> = HashMap::new();
+
+        builder.write(writer, |_| {
+            while next < files.len()
+                && next - written < max_in_flight
+                && (next == written || buffered + files[next].size <= options.buffer_size)
+            {
+                job_sender.send(next).map_err(|_| anyhow!("The encoding threads stopped unexpectedly"))?;
+                buffered += files[next].size;
+                next += 1;
+            }
+
+            let stored = loop {
+                if let Some(stored) = done.remove(&written) {
+                    break stored;
+                }
+                let (index, stored) = done_receiver.recv()?;
+                done.insert(index, stored);
+            };
+            buffered -= files[written].size;
+            written += 1;
+            stored
+        })
+    })
+}
+
+/// Reads the file at `file.path` inside `folder` and encodes it.
+fn encode_file(folder: &Path, file: &PackedFile, options: &PackOptions) -> Result<StoredFile> {
+    let data = fs::read(folder.join(&file.path)).with_context(|| format!("Failed to read '{}'", file.path))?;
+    StoredFile::encode(&data, file.storage_type, file.verification_type, &options.codecs, file.level)
+}
+
+/// Adds the path relative to the packed folder and the size of every file below `folder` to `paths`.
+/// `relative` is the path of `folder` itself, separated by `/`.
+fn list_files(folder: &Path, relative: &str, paths: &mut Vec<(String, u64)>) -> Result<()> {
+    let entries = fs::read_dir(folder).with_context(|| format!("Failed to read the folder '{}'", folder.display()))?;
+    for entry in entries {
+        let entry = entry?;
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            bail!("The name of '{}' is not valid UTF-8", entry.path().display());
+        };
+        let path = match relative.is_empty() {
+            true => name,
+            false => format!("{}/{}", relative, name),
+        };
+
+        let metadata = fs::metadata(entry.path())?;
+        match metadata.is_dir() {
+            true => list_files(&entry.path(), &path, paths)?,
+            false => paths.push((path, metadata.len())),
+        }
+    }
+
+    Ok(())
+}
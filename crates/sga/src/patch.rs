@@ -0,0 +1,306 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{BufReader, BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    archive::SgaArchive,
+    builder::{normalize_path, ArchiveBuilder, PlacedFile, StoredFile},
+    entires::SgaFileEntry,
+    layout::ArchiveLayout,
+    options::ExtractOptions,
+    pack::{block_hash_length, ArchiveWriter},
+};
+
+/// A file of an `ArchivePatcher`.
+#[derive(Debug, Clone)]
+enum Patched {
+    /// The file entry at this index of the archive, whose data stays where it is.
+    Kept(usize),
+
+    /// A file whose data was appended to the data blob.
+    Appended(PlacedFile),
+}
+
+/// What `ArchivePatcher::commit` did.
+#[derive(Debug, Clone, Default)]
+pub struct PatchReport {
+    /// Number of files added.
+    pub added: usize,
+
+    /// Number of files replaced.
+    pub replaced: usize,
+
+    /// Number of files removed.
+    pub removed: usize,
+
+    /// Size of the data appended to the data blob.
+    pub appended_bytes: u64,
+
+    /// Size of the parts of the data blob no file entry points at anymore, which `compact` reclaims.
+    pub dead_bytes: u64,
+}
+
+/// Replaces, adds and removes files of an archive file in place, without writing the data of the other files again.
+///
+/// The stored data of every new file is appended at the end of the file as it's given. `commit` then writes a new header blob
+/// after it and rewrites the header, so the data blob grows over the old header blob and nothing else is moved.
+/// Until the header is rewritten the archive stays readable as it was. The data of replaced and removed files and the old
+/// header blob are left in the data blob as dead space, which `compact` reclaims.
+///
+/// ```no_run
+/// use sga::{builder::StoredFile, codec::CodecRegistry, entires::{FileStorageType, FileVerificationType}, patch::ArchivePatcher};
+///
+/// let mut patcher = ArchivePatcher::open("./Attrib.sga")?;
+/// let data = std::fs::read("./ebps/races/english/units/villager.rgd")?;
+/// let stored = StoredFile::encode(&data, FileStorageType::BufferCompress, FileVerificationType::CRC, &CodecRegistry::default(), None)?;
+/// patcher.replace("attrib", "ebps/races/english/units/villager.rgd", &stored)?;
+/// patcher.commit()?;
+/// # anyhow::Ok(())
+/// ```
+#[derive(Debug)]
+pub struct ArchivePatcher {
+    file: File,
+    layout: ArchiveLayout,
+    builder: ArchiveBuilder<Patched>,
+    /// Where the next appended data is written, from the start of the file.
+    end: u64,
+    report: PatchReport,
+}
+
+impl ArchivePatcher {
+    /// Opens the archive at `path` for patching.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("Failed to open '{}'", path.display()))?;
+
+        let mut archive = SgaArchive::from_reader(BufReader::new(file.try_clone()?))?;
+        let layout = archive.layout()?;
+        let plan = archive.plan(&ExtractOptions::default())?;
+
+        let mut builder = ArchiveBuilder::with_header(&layout.header);
+        for toc in archive.tocs() {
+            builder.add_toc(&toc.alias, &toc.name);
+        }
+        for planned in &plan.files {
+            let index = planned.file.index().with_context(|| format!("'{}' has no file entry", planned.archive_path))?;
+            builder.add_file(&planned.toc, &planned.archive_path, Patched::Kept(index));
+        }
+
+        let end = file.metadata()?.len().max(layout.header.data_offset + layout.header.data_blob_length);
+        Ok(Self { file, layout, builder, end, report: PatchReport::default() })
+    }
+
+    /// The file entry of the file at `path` inside the table of contents with the alias `toc`, ignoring case.
+    /// The name and hash offsets of files added or replaced since the archive was opened are zero.
+    pub fn file_entry(&self, toc: &str, path: &str) -> Option<&SgaFileEntry> {
+        let (toc, path) = self.find(toc, path)?;
+        match self.builder.file(&toc, &path)? {
+            Patched::Kept(index) => self.layout.files.get(*index),
+            Patched::Appended(placed) => Some(&placed.entry),
+        }
+    }
+
+    /// Replaces the data of the file at `path` inside the table of contents with the alias `toc`, ignoring case.
+    /// Fails if there is no such file.
+    pub fn replace(&mut self, toc: &str, path: &str, stored: &StoredFile) -> Result<()> {
+        let Some((toc, path)) = self.find(toc, path) else {
+            bail!("There is no file '{}/{}' to replace", toc, path);
+        };
+
+        let placed = self.append(stored)?;
+        self.builder.add_file(&toc, &path, Patched::Appended(placed));
+        self.report.replaced += 1;
+        Ok(())
+    }
+
+    /// Adds a file at `path` inside the table of contents with the alias `toc`, the folders it's in are added if needed.
+    /// Fails if there already is a file at the same path, ignoring case, or no such table of contents.
+    pub fn add(&mut self, toc: &str, path: &str, stored: &StoredFile) -> Result<()> {
+        if self.find(toc, path).is_some() {
+            bail!("There already is a file '{}/{}'", toc, path);
+        }
+        let Some(alias) = self.layout.tocs.iter().find(|other| other.alias.eq_ignore_ascii_case(toc)).map(|toc| toc.alias.clone()) else {
+            bail!("The archive has no table of contents with alias '{}'", toc);
+        };
+
+        let placed = self.append(stored)?;
+        self.builder.add_file(&alias, path, Patched::Appended(placed));
+        self.report.added += 1;
+        Ok(())
+    }
+
+    /// Removes the file at `path` inside the table of contents with the alias `toc`, ignoring case.
+    /// Folders left empty are removed with it. Fails if there is no such file.
+    pub fn remove(&mut self, toc: &str, path: &str) -> Result<()> {
+        let Some((toc, path)) = self.find(toc, path) else {
+            bail!("There is no file '{}/{}' to remove", toc, path);
+        };
+
+        self.builder.remove_file(&toc, &path);
+        self.report.removed += 1;
+        Ok(())
+    }
+
+    /// Writes the new header blob and header, and returns what was patched.
+    ///
+    /// The tables of contents, folders, files and strings are laid out again like `ArchiveBuilder` does,
+    /// the data of the files which weren't replaced and their block hashes are kept as they are.
+    /// As the folders are made from the paths of the files, the entries of every folder end up sorted by name
+    /// and folders without any file below them are left out, even when nothing was changed.
+    pub fn commit(mut self) -> Result<PatchReport> {
+        let hashes = self.layout.hash_blob()?;
+        let old = &self.layout;
+
+        let mut layout = self.builder.build(|patched| match patched {
+            Patched::Kept(index) => {
+                let entry = &old.files[*index];
+                let start = entry.hash_offset as usize;
+                let length = block_hash_length(entry.verification_type, entry.compressed_length as u64, old.header.block_size);
+                let Some(hashes) = hashes.get(start..start + length as usize) else {
+                    bail!("The block hashes of file entry {} are past the end of the hash blob", index);
+                };
+                Ok(PlacedFile { entry: entry.clone(), hashes: hashes.to_vec() })
+            }
+            Patched::Appended(placed) => Ok(placed.clone()),
+        })?;
+
+        layout.header.signature = old.header.signature;
+        layout.arrange_header_blob()?;
+        let header = &mut layout.header;
+        header.data_offset = old.header.data_offset;
+        header.data_blob_length = self.end - header.data_offset;
+        header.header_blob_offset = self.end;
+
+        self.file.seek(SeekFrom::Start(self.end))?;
+        self.file.write_all(&layout.header_blob()?)?;
+        self.file.sync_data()?;
+        self.file.seek(SeekFrom::Start(0))?;
+        layout.header.write(&mut self.file)?;
+        self.file.sync_all()?;
+
+        self.report.dead_bytes = dead_bytes(&layout);
+        Ok(self.report)
+    }
+
+    /// Appends `stored` at the end of the file.
+    fn append(&mut self, stored: &StoredFile) -> Result<PlacedFile> {
+        let placed = stored.placed(self.end - self.layout.header.data_offset, self.layout.header.block_size)?;
+
+        self.file.seek(SeekFrom::Start(self.end))?;
+        self.file.write_all(&stored.data)?;
+        self.end += stored.data.len() as u64;
+        self.report.appended_bytes += stored.data.len() as u64;
+
+        Ok(placed)
+    }
+
+    /// The alias of the table of contents `toc` and the path of the file at `path` inside it, as they're spelled in the archive.
+    /// A file spelled exactly like `path` is preferred over the other files at the same path ignoring case.
+    fn find(&self, toc: &str, path: &str) -> Option<(String, String)> {
+        let path = normalize_path(path);
+        let mut matching = self
+            .builder
+            .files()
+            .filter(|(alias, other, _)| alias.eq_ignore_ascii_case(toc) && other.eq_ignore_ascii_case(&path));
+        let first = matching.next()?;
+        let found = std::iter::once(first).chain(matching).find(|(_, other, _)| *other == path).unwrap_or(first);
+
+        Some((found.0.to_string(), found.1.to_string()))
+    }
+}
+
+/// What `compact` did.
+#[derive(Debug, Clone, Default)]
+pub struct CompactReport {
+    /// Size of the archive file before it was compacted.
+    pub bytes_before: u64,
+
+    /// Size of the archive file after it was compacted.
+    pub bytes_after: u64,
+}
+
+/// Rewrites the archive at `path` without the dead space left by `ArchivePatcher`, or any other data no file entry points at.
+///
+/// The archive is written into a temporary file next to it, which then replaces it. Only the data offsets of the file entries
+/// change, entries sharing the same data keep sharing it and files with identical data share it from then on.
+pub fn compact<P: AsRef<Path>>(path: P) -> Result<CompactReport> {
+    let path = path.as_ref();
+    let bytes_before = fs::metadata(path)?.len();
+    let temporary = temporary_path(path)?;
+
+    let written = write_compacted(path, &temporary);
+    if let Err(err) = written {
+        let _ = fs::remove_file(&temporary);
+        return Err(err);
+    }
+    fs::rename(&temporary, path).with_context(|| format!("Failed to replace '{}'", path.display()))?;
+
+    Ok(CompactReport { bytes_before, bytes_after: fs::metadata(path)?.len() })
+}
+
+/// Writes the archive at `path` into `output` with the data of its files one after another.
+fn write_compacted(path: &Path, output: &Path) -> Result<()> {
+    let mut archive = SgaArchive::open(path)?;
+    let mut layout = archive.layout()?;
+
+    let file = File::create(output).with_context(|| format!("Failed to create '{}'", output.display()))?;
+    let mut writer = ArchiveWriter::new(BufWriter::new(file))?;
+    let mut copied: HashMap<(u64, u32), u64> = HashMap::new();
+    for index in 0..layout.files.len() {
+        let entry = &layout.files[index];
+        let key = (entry.data_offset, entry.compressed_length);
+        let data_offset = match copied.get(&key) {
+            Some(&data_offset) => data_offset,
+            None => {
+                let data_offset = writer.add_data(&archive.read_stored(index)?)?;
+                copied.insert(key, data_offset);
+                data_offset
+            }
+        };
+        layout.files[index].data_offset = data_offset;
+    }
+
+    let (writer, _) = writer.finish(layout)?;
+    writer.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+
+    Ok(())
+}
+
+/// A hidden path next to `path` to write its new contents into.
+fn temporary_path(path: &Path) -> Result<PathBuf> {
+    let Some(name) = path.file_name() else {
+        bail!("'{}' is not a file", path.display());
+    };
+    Ok(path.with_file_name(format!(".{}.compact", name.to_string_lossy())))
+}
+
+/// The number of bytes of the data blob of `layout` no file entry points at.
+fn dead_bytes(layout: &ArchiveLayout) -> u64 {
+    let mut regions: Vec<(u64, u64)> = layout
+        .files
+        .iter()
+        .map(|entry| (entry.data_offset, entry.data_offset + entry.compressed_length as u64))
+        .collect();
+    regions.sort_unstable();
+
+    let mut live = 0;
+    let mut covered = 0;
+    for (start, end) in regions {
+        let start = start.max(covered);
+        if end > start {
+            live += end - start;
+            covered = end;
+        }
+    }
+
+    layout.header.data_blob_length.saturating_sub(live)
+}
@@ -0,0 +1,272 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    archive::TocLayout,
+    nodes::{FileNode, FolderNode, Node, Toc},
+    options::{ExtractOptions, FileFilter},
+};
+
+/// A file which will be written by an extraction.
+#[derive(Debug, Clone)]
+pub struct PlannedFile {
+    /// Alias of the table of contents the file belongs to, empty if unknown.
+    pub toc: String,
+
+    /// Path of the file inside its table of contents, separated by `/`.
+    pub archive_path: String,
+
+    /// Path the file is written to, relative to the root of the output.
+    pub path: PathBuf,
+
+    /// The file in the archive.
+    pub file: Arc<FileNode>,
+}
+
+/// Every folder and file an extraction will write, in the order they are written.
+#[derive(Debug, Clone, Default)]
+pub struct ExtractPlan {
+    /// Folders to create, relative to the root of the output. Parents come before their children.
+    pub folders: Vec<PathBuf>,
+
+    /// Files to write.
+    pub files: Vec<PlannedFile>,
+}
+
+impl ExtractPlan {
+    /// Plans the extraction of the given tables of contents, applying the selection of `options`.
+    pub fn for_tocs(tocs: &[&Toc], options: &ExtractOptions) -> Result<Self> {
+        let mut plan = Self::default();
+        let mut subtree_found = false;
+        let filter = options.file_filter()?;
+
+        for toc in tocs {
+            let toc_path = match options.toc_layout {
+                TocLayout::Subdirectory => PathBuf::from(sanitize_name(toc.directory_name())?.as_ref()),
+                TocLayout::Merged => PathBuf::new(),
+            };
+
+            subtree_found |= plan.add_folder(toc.root_folder.clone(), &toc.alias, &toc_path, options, &filter)?;
+        }
+
+        if let (Some(subtree), false) = (&options.subtree, subtree_found) {
+            bail!("The folder '{}' does not exist in the selected tables of contents", subtree);
+        }
+
+        plan.prune_folders(&filter);
+        Ok(plan)
+    }
+
+    /// Plans the extraction of `folder` into `parent`, applying the selection of `options`.
+    pub fn for_folder(folder: Arc<Mutex<FolderNode>>, parent: &Path, options: &ExtractOptions) -> Result<Self> {
+        let mut plan = Self::default();
+        let filter = options.file_filter()?;
+
+        if !plan.add_folder(folder, "", parent, options, &filter)? {
+            bail!("The folder '{}' does not exist", options.subtree.as_deref().unwrap_or_default());
+        }
+
+        plan.prune_folders(&filter);
+        Ok(plan)
+    }
+
+    /// Total size of the planned files once decoded, in bytes.
+    pub fn total_bytes(&self) -> u64 {
+        self.files.iter().map(|planned| planned.file.size() as u64).sum()
+    }
+
+    /// Total size of the planned files as stored in the archive, in bytes.
+    pub fn stored_bytes(&self) -> u64 {
+        self.files.iter().map(|planned| planned.file.stored_size() as u64).sum()
+    }
+
+    /// For every planned file, the position of the first planned file sharing its data with it, if it isn't that first file itself.
+    pub fn shared_data(&self) -> Vec<Option<usize>> {
+        shared_data(&self.files)
+    }
+
+    /// Adds the selected part of the tree under `root` to the plan, written into `parent`.
+    /// Returns false if the selected subtree doesn't exist under `root`.
+    fn add_folder(&mut self, root: Arc<Mutex<FolderNode>>, toc: &str, parent: &Path, options: &ExtractOptions, filter: &FileFilter) -> Result<bool> {
+        let root_path = parent.join(sanitize_name(&root.lock().unwrap().name)?.as_ref());
+
+        let subtree = options.subtree.as_deref().map(split_archive_path).unwrap_or_default();
+        let Some((start, subtree)) = find_folder(root, &subtree) else {
+            return Ok(false);
+        };
+
+        let archive_path = subtree.join("/");
+        let mut out_path = root_path;
+        if !options.flatten {
+            for name in &subtree {
+                out_path.push(sanitize_name(name)?.as_ref());
+            }
+        }
+
+        if options.flatten {
+            self.folders.push(out_path.clone());
+        }
+
+        let selection = Selection { toc, options, filter };
+        self.add_tree(start, &out_path, &archive_path, 0, &selection)?;
+        Ok(true)
+    }
+
+    fn add_tree(&mut self, folder: Arc<Mutex<FolderNode>>, out_path: &Path, archive_path: &str, depth: usize, selection: &Selection) -> Result<()> {
+        let options = selection.options;
+        if !options.flatten {
+            self.folders.push(out_path.to_path_buf());
+        }
+
+        let folder = folder.lock().unwrap();
+        for child in &folder.children {
+            match child {
+                Node::File(file) => {
+                    let file_archive_path = join_archive_path(archive_path, &file.name);
+                    if !selection.filter.matches(&file_archive_path) {
+                        continue;
+                    }
+
+                    let name = sanitize_name(&file.name)?;
+                    if name.is_empty() {
+                        bail!("A file in '{}' has no name", archive_path);
+                    }
+
+                    self.files.push(PlannedFile {
+                        toc: selection.toc.to_string(),
+                        path: out_path.join(name.as_ref()),
+                        archive_path: file_archive_path,
+                        file: file.clone(),
+                    });
+                }
+                Node::Folder(subfolder) => {
+                    if options.max_depth.is_some_and(|max_depth| depth >= max_depth) {
+                        continue;
+                    }
+
+                    let name = subfolder.lock().unwrap().name.clone();
+                    let subfolder_out = match options.flatten {
+                        true => out_path.to_path_buf(),
+                        false => out_path.join(sanitize_name(&name)?.as_ref()),
+                    };
+
+                    self.add_tree(subfolder.clone(), &subfolder_out, &join_archive_path(archive_path, &name), depth + 1, selection)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Leaves out the folders without any selected file below them when files are filtered by pattern.
+    fn prune_folders(&mut self, filter: &FileFilter) {
+        if !filter.is_active() {
+            return;
+        }
+
+        let files = &self.files;
+        self.folders.retain(|folder| files.iter().any(|planned| planned.path.starts_with(folder)));
+    }
+}
+
+/// For every file of `files`, the position of the first file sharing its data with it, if it isn't that first file itself.
+pub(crate) fn shared_data(files: &[PlannedFile]) -> Vec<Option<usize>> {
+    let mut first = HashMap::new();
+    files
+        .iter()
+        .enumerate()
+        .map(|(position, planned)| {
+            let original = *first.entry(planned.file.data_key()).or_insert(position);
+            (original != position).then_some(original)
+        })
+        .collect()
+}
+
+/// What is selected from a table of contents while planning.
+struct Selection<'a> {
+    toc: &'a str,
+    options: &'a ExtractOptions,
+    filter: &'a FileFilter,
+}
+
+/// Makes a file or folder name from an archive safe to use as a single component of an output path.
+/// Separators, drive colons and control characters are replaced by `_`, and `.` and `..` are refused,
+/// so an archive can never write outside of the output.
+pub fn sanitize_name(name: &str) -> Result<Cow<'_, str>> {
+    if name == "." || name == ".." {
+        bail!("The archive contains the unsafe name '{}'", name);
+    }
+
+    let is_unsafe = |c: char| matches!(c, '/' | '\\' | ':') || c.is_control();
+    Ok(match name.contains(is_unsafe) {
+        true => Cow::Owned(name.replace(is_unsafe, "_")),
+        false => Cow::Borrowed(name),
+    })
+}
+
+/// Splits a path inside an archive into its components, accepting both `/` and `\` as separators.
+pub fn split_archive_path(path: &str) -> Vec<&str> {
+    path.split(['/', '\\']).filter(|name| !name.is_empty() && *name != ".").collect()
+}
+
+fn join_archive_path(parent: &str, name: &str) -> String {
+    match parent.is_empty() {
+        true => name.to_string(),
+        false => format!("{}/{}", parent, name),
+    }
+}
+
+/// Finds the folder at `path` below `root`, comparing names without case, as the games do.
+/// Returns the folder and the names of the folders on the way, as spelled in the archive.
+fn find_folder(root: Arc<Mutex<FolderNode>>, path: &[&str]) -> Option<(Arc<Mutex<FolderNode>>, Vec<String>)> {
+    let mut folder = root;
+    let mut names = Vec::with_capacity(path.len());
+
+    for wanted in path {
+        let next = folder.lock().unwrap().children.iter().find_map(|child| match child {
+            Node::Folder(subfolder) => {
+                let name = subfolder.lock().unwrap().name.clone();
+                name.eq_ignore_ascii_case(wanted).then(|| (subfolder.clone(), name))
+            }
+            _ => None,
+        })?;
+
+        folder = next.0;
+        names.push(next.1);
+    }
+
+    Some((folder, names))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_name_refuses_dot_names() {
+        assert!(sanitize_name(".").is_err());
+        assert!(sanitize_name("..").is_err());
+        assert_eq!(sanitize_name("...").unwrap(), "...");
+        assert_eq!(sanitize_name(".hidden").unwrap(), ".hidden");
+    }
+
+    #[test]
+    fn sanitize_name_replaces_separators_colons_and_control_characters() {
+        assert_eq!(sanitize_name("../evil").unwrap(), ".._evil");
+        assert_eq!(sanitize_name("..\\evil").unwrap(), ".._evil");
+        assert_eq!(sanitize_name("/etc/passwd").unwrap(), "_etc_passwd");
+        assert_eq!(sanitize_name("C:evil").unwrap(), "C_evil");
+        assert_eq!(sanitize_name("a\0b\nc\x7f").unwrap(), "a_b_c_");
+    }
+
+    #[test]
+    fn sanitize_name_borrows_safe_names() {
+        assert!(matches!(sanitize_name("villager.rgd").unwrap(), Cow::Borrowed("villager.rgd")));
+    }
+}
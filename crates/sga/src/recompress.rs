@@ -0,0 +1,173 @@
+use std::io::{BufRead, Read, Seek, Write};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    archive::SgaArchive,
+    codec::CodecRegistry,
+    entires::{FileStorageType, FileVerificationType},
+    pack::{block_hashes, ArchiveWriter},
+    utils::to_hex,
+};
+
+/// Chooses the storage type every file is re-encoded with by `recompress`.
+///
+/// A rule for the extension of a file takes precedence over the size rules, which take precedence over the default.
+/// Files no rule applies to keep their storage type if there is no default.
+///
+/// ```no_run
+/// use std::{fs::File, io::BufWriter};
+/// use sga::{archive::SgaArchive, entires::FileStorageType, recompress::{recompress, StoragePolicy}};
+///
+/// let policy = StoragePolicy::new(FileStorageType::BufferCompress)
+///     .extension("dds", FileStorageType::Store)
+///     .smaller_than(512, FileStorageType::Store);
+/// let output = BufWriter::new(File::create("./Attrib.deflate.sga")?);
+/// recompress(&mut SgaArchive::open("./Attrib.sga")?, output, &policy)?;
+/// # anyhow::Ok(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct StoragePolicy {
+    default: Option<FileStorageType>,
+    extensions: Vec<(String, FileStorageType)>,
+    sizes: Vec<(u64, FileStorageType)>,
+    level: Option<u32>,
+    codecs: CodecRegistry,
+}
+
+impl StoragePolicy {
+    /// Stores every file as `storage_type`, unless a rule says otherwise.
+    pub fn new(storage_type: FileStorageType) -> Self {
+        Self { default: Some(storage_type), ..Self::default() }
+    }
+
+    /// Keeps the storage type of every file, unless a rule says otherwise.
+    pub fn keep() -> Self {
+        Self::default()
+    }
+
+    /// Stores files with `extension`, for example `dds`, as `storage_type`. Extensions are compared ignoring case.
+    pub fn extension<S: AsRef<str>>(mut self, extension: S, storage_type: FileStorageType) -> Self {
+        let extension = extension.as_ref().trim_start_matches('.').to_lowercase();
+        self.extensions.retain(|(other, _)| *other != extension);
+        self.extensions.push((extension, storage_type));
+        self
+    }
+
+    /// Stores files smaller than `size` bytes once decoded as `storage_type`.
+    /// When several size rules apply to a file, the one with the smallest size is used.
+    pub fn smaller_than(mut self, size: u64, storage_type: FileStorageType) -> Self {
+        self.sizes.retain(|(other, _)| *other != size);
+        self.sizes.push((size, storage_type));
+        self.sizes.sort_by_key(|(size, _)| *size);
+        self
+    }
+
+    /// Sets the codec specific compression level files are encoded with, `None` uses each codec's default.
+    pub fn level(mut self, level: Option<u32>) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Sets the codecs used to decode and encode file data.
+    pub fn codecs(mut self, codecs: CodecRegistry) -> Self {
+        self.codecs = codecs;
+        self
+    }
+
+    /// The storage type for the file named `name` with `size` bytes once decoded, or `None` if it keeps its storage type.
+    pub fn storage_type(&self, name: &str, size: u64) -> Option<FileStorageType> {
+        let extension = name.rsplit_once('.').map(|(_, extension)| extension.to_lowercase());
+        let by_extension = extension.and_then(|extension| {
+            self.extensions
+                .iter()
+                .find(|(other, _)| *other == extension)
+                .map(|(_, storage_type)| *storage_type)
+        });
+        let by_size = self.sizes.iter().find(|(limit, _)| size < *limit).map(|(_, storage_type)| *storage_type);
+
+        by_extension.or(by_size).or(self.default)
+    }
+}
+
+/// What `recompress` did.
+#[derive(Debug, Clone, Default)]
+pub struct RecompressReport {
+    /// Number of file entries written.
+    pub files: usize,
+
+    /// Number of files whose storage type changed, which were decoded and encoded again.
+    /// The data of the other files was copied as is.
+    pub reencoded: usize,
+
+    /// Size of the data blob of the input archive.
+    pub stored_bytes_before: u64,
+
+    /// Size of the data blob of the written archive.
+    pub stored_bytes_after: u64,
+
+    /// Size of the stored data several files share, which is only stored once.
+    pub shared_bytes: u64,
+}
+
+/// Writes `archive` into `writer` with every file stored as `policy` chooses.
+///
+/// The tables of contents, folders and files stay the same and in the same order, only the data of the files changes.
+/// The CRC of every file and the hash blob are computed again from the new data, and files with identical new data share it.
+/// The data of files with the CRC verification type is checked before it's decoded.
+pub fn recompress<R, W>(archive: &mut SgaArchive<R>, writer: W, policy: &StoragePolicy) -> Result<RecompressReport>
+where
+    R: Read + BufRead + Seek,
+    W: Write + Seek,
+{
+    let mut layout = archive.layout()?;
+    let names: Vec<String> = {
+        let strings = layout.strings_by_offset();
+        layout
+            .files
+            .iter()
+            .map(|entry| strings.get(&entry.name_offset).copied().unwrap_or_default().to_string())
+            .collect()
+    };
+
+    let mut report = RecompressReport { files: layout.files.len(), stored_bytes_before: layout.header.data_blob_length, ..Default::default() };
+    let block_size = layout.header.block_size;
+    let mut writer = ArchiveWriter::new(writer)?;
+    let mut hashes = Vec::new();
+
+    for (index, entry) in layout.files.iter_mut().enumerate() {
+        let name = &names[index];
+        let mut stored = archive.read_stored(index)?;
+
+        if entry.verification_type == FileVerificationType::CRC && crc32fast::hash(&stored) != entry.crc {
+            bail!("CRC mismatch in '{}', the archive is corrupt", name);
+        }
+
+        let storage_type = policy.storage_type(name, entry.uncompressed_size as u64).unwrap_or(entry.storage_type);
+        if storage_type != entry.storage_type {
+            let data = policy
+                .codecs
+                .decode(entry.storage_type, &stored, entry.uncompressed_size as usize)
+                .with_context(|| format!("Failed to decode '{}'", name))?;
+            stored = policy
+                .codecs
+                .encode(storage_type, &data, policy.level)
+                .with_context(|| format!("Failed to encode '{}'", name))?;
+            report.reencoded += 1;
+        }
+
+        entry.storage_type = storage_type;
+        entry.compressed_length = u32::try_from(stored.len()).with_context(|| format!("'{}' is too large once encoded", name))?;
+        entry.crc = crc32fast::hash(&stored);
+        entry.data_offset = writer.add_data(&stored)?;
+        entry.hash_offset = hashes.len() as u32;
+        hashes.extend(block_hashes(entry.verification_type, &stored, block_size));
+    }
+
+    layout.file_hashes = to_hex(&hashes);
+    let (_, layout) = writer.finish(layout)?;
+    report.stored_bytes_after = layout.header.data_blob_length;
+    report.shared_bytes = layout.shared_bytes();
+
+    Ok(report)
+}
@@ -0,0 +1,128 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use super::ExtractSink;
+use crate::options::LinkMode;
+
+/// Writes extracted files into a folder on disk.
+#[derive(Debug, Clone)]
+pub struct DirectorySink {
+    root: PathBuf,
+    atomic_files: bool,
+}
+
+impl DirectorySink {
+    /// Creates a sink writing into `root`, which is created if it doesn't exist.
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self { root: root.as_ref().to_path_buf(), atomic_files: false }
+    }
+
+    /// Writes every file into a temporary file next to it first, which is flushed to disk and then renamed over it.
+    /// A file is then either missing, the previous version or completely written, never partially written.
+    pub fn with_atomic_files(mut self, atomic_files: bool) -> Self {
+        self.atomic_files = atomic_files;
+        self
+    }
+
+    /// The folder files are written into.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+impl ExtractSink for DirectorySink {
+    fn create_dir(&mut self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(self.root.join(path))
+    }
+
+    fn write_file(&mut self, path: &Path, _size: u64, data: &mut dyn Read) -> io::Result<u64> {
+        let path = self.root.join(path);
+        if !self.atomic_files {
+            // Writing into a hard link would change the files linked to it too.
+            remove_if_exists(&path)?;
+            return write_to(&path, data, false);
+        }
+
+        let temp_path = temp_path_for(&path);
+        let result = write_to(&temp_path, data, true).and_then(|written| {
+            fs::rename(&temp_path, &path)?;
+            Ok(written)
+        });
+
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
+    }
+
+    fn link_file(&mut self, original: &Path, path: &Path, mode: LinkMode) -> io::Result<bool> {
+        if mode == LinkMode::None {
+            return Ok(false);
+        }
+
+        let original = self.root.join(original);
+        let path = self.root.join(path);
+        // Both paths are the same file on case-insensitive file systems, which already has the contents.
+        if path.try_exists()? && fs::canonicalize(&path)? == fs::canonicalize(&original)? {
+            return Ok(true);
+        }
+
+        let target = match self.atomic_files {
+            true => temp_path_for(&path),
+            false => path.clone(),
+        };
+        let result = match mode {
+            // File systems without hard links get the file written as usual instead.
+            LinkMode::Hardlink => remove_if_exists(&target).map(|()| fs::hard_link(&original, &target).is_ok()),
+            // File systems which can't share blocks between files get a copy of the file instead.
+            _ => remove_if_exists(&target).and_then(|()| reflink_copy::reflink_or_copy(&original, &target)).map(|_| true),
+        };
+        let result = result.and_then(|linked| {
+            if linked && self.atomic_files {
+                fs::rename(&target, &path)?;
+            }
+            Ok(linked)
+        });
+
+        if self.atomic_files && !matches!(result, Ok(true)) {
+            let _ = fs::remove_file(&target);
+        }
+        result
+    }
+
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        self.root.join(path).try_exists()
+    }
+
+    fn remove_file(&mut self, path: &Path) -> io::Result<()> {
+        fs::remove_file(self.root.join(path))
+    }
+}
+
+fn write_to(path: &Path, data: &mut dyn Read, sync: bool) -> io::Result<u64> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let written = io::copy(data, &mut file)?;
+    file.flush()?;
+
+    if sync {
+        file.get_ref().sync_all()?;
+    }
+
+    Ok(written)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// A hidden file next to `path`, unique to this process.
+fn temp_path_for(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()))
+}
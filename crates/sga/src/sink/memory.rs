@@ -0,0 +1,50 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use super::ExtractSink;
+use crate::codec::ExactLengthReader;
+
+/// Keeps extracted files in memory, keyed by their path.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySink {
+    /// The extracted files.
+    pub files: HashMap<PathBuf, Vec<u8>>,
+}
+
+impl MemorySink {
+    /// Creates an empty sink.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the extracted files.
+    pub fn into_files(self) -> HashMap<PathBuf, Vec<u8>> {
+        self.files
+    }
+}
+
+impl ExtractSink for MemorySink {
+    fn create_dir(&mut self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_file(&mut self, path: &Path, size: u64, data: &mut dyn Read) -> io::Result<u64> {
+        let mut contents = Vec::with_capacity(size as usize);
+        let written = ExactLengthReader::new(data, size).read_to_end(&mut contents)?;
+        self.files.insert(path.to_path_buf(), contents);
+
+        Ok(written as u64)
+    }
+
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        Ok(self.files.contains_key(path))
+    }
+
+    fn remove_file(&mut self, path: &Path) -> io::Result<()> {
+        self.files.remove(path);
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+mod directory;
+pub use directory::*;
+
+mod memory;
+pub use memory::*;
+
+mod tar;
+pub use self::tar::*;
+
+mod zip;
+pub use self::zip::*;
+
+use std::{
+    io::{self, Read},
+    path::{Component, Path},
+};
+
+use crate::options::LinkMode;
+
+/// Destination extracted folders and files are written to.
+///
+/// Paths passed to a sink are relative to the root of the extraction, and always use the archive's folder structure.
+pub trait ExtractSink {
+    /// Creates the folder at `path`, `path` is empty for the root of the extraction.
+    fn create_dir(&mut self, path: &Path) -> io::Result<()>;
+
+    /// Writes a file at `path`, reading exactly `size` bytes from `data`. Returns the number of bytes written.
+    fn write_file(&mut self, path: &Path, size: u64, data: &mut dyn Read) -> io::Result<u64>;
+
+    /// Writes a file at `path` with the contents of the file already written at `original`, as `mode` asks,
+    /// without reading its data again. Returns false if the sink can't, the file is then written with `write_file`.
+    fn link_file(&mut self, _original: &Path, _path: &Path, _mode: LinkMode) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    /// Whether a file already exists at `path`, sinks which always start out empty return false.
+    fn exists(&self, _path: &Path) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    /// Removes a file which was only partially written, sinks which can not remove files do nothing.
+    fn remove_file(&mut self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called once everything has been written, to flush any buffered output.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S: ExtractSink + ?Sized> ExtractSink for &mut S {
+    fn create_dir(&mut self, path: &Path) -> io::Result<()> {
+        (**self).create_dir(path)
+    }
+
+    fn write_file(&mut self, path: &Path, size: u64, data: &mut dyn Read) -> io::Result<u64> {
+        (**self).write_file(path, size, data)
+    }
+
+    fn link_file(&mut self, original: &Path, path: &Path, mode: LinkMode) -> io::Result<bool> {
+        (**self).link_file(original, path, mode)
+    }
+
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        (**self).exists(path)
+    }
+
+    fn remove_file(&mut self, path: &Path) -> io::Result<()> {
+        (**self).remove_file(path)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        (**self).finish()
+    }
+}
+
+/// Joins the components of `path` with forward slashes, as expected inside tar and zip files.
+pub(crate) fn to_archive_path(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
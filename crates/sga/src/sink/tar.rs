@@ -0,0 +1,61 @@
+use std::{
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use tar::{Builder, EntryType, Header};
+
+use super::{to_archive_path, ExtractSink};
+use crate::codec::ExactLengthReader;
+
+/// Streams extracted files into a tar archive, for example to stdout.
+///
+/// Entries are written with fixed permissions and a zero modification time, so the output only depends on the archive.
+pub struct TarSink<W: Write> {
+    builder: Builder<W>,
+}
+
+impl<W: Write> TarSink<W> {
+    /// Creates a sink writing a tar archive into `writer`.
+    pub fn new(writer: W) -> Self {
+        Self { builder: Builder::new(writer) }
+    }
+
+    /// Finishes the tar archive and returns the underlying writer.
+    pub fn into_inner(self) -> io::Result<W> {
+        self.builder.into_inner()
+    }
+}
+
+impl<W: Write> ExtractSink for TarSink<W> {
+    fn create_dir(&mut self, path: &Path) -> io::Result<()> {
+        let path = to_archive_path(path);
+        if path.is_empty() {
+            return Ok(());
+        }
+
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Directory);
+        header.set_mode(0o755);
+        header.set_size(0);
+        header.set_mtime(0);
+        self.builder.append_data(&mut header, format!("{}/", path), io::empty())
+    }
+
+    fn write_file(&mut self, path: &Path, size: u64, data: &mut dyn Read) -> io::Result<u64> {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Regular);
+        header.set_mode(0o644);
+        header.set_size(size);
+        header.set_mtime(0);
+        // The size is written into the header before the data, so data of another length would corrupt the entries after it.
+        self.builder.append_data(&mut header, to_archive_path(path), ExactLengthReader::new(data, size))?;
+
+        Ok(size)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.builder.finish()?;
+        self.builder.get_mut().flush()
+    }
+}
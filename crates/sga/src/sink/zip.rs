@@ -0,0 +1,70 @@
+use std::{
+    io::{self, Read, Seek, Write},
+    path::Path,
+};
+
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use super::{to_archive_path, ExtractSink};
+use crate::codec::ExactLengthReader;
+
+/// Writes extracted files into a zip file.
+pub struct ZipSink<W: Write + Seek> {
+    writer: Option<ZipWriter<W>>,
+    finished: Option<W>,
+    options: SimpleFileOptions,
+}
+
+impl<W: Write + Seek> ZipSink<W> {
+    /// Creates a sink writing a deflate compressed zip file into `writer`.
+    pub fn new(writer: W) -> Self {
+        Self::with_compression(writer, CompressionMethod::Deflated)
+    }
+
+    /// Creates a sink writing a zip file into `writer`, compressing the entries with `method`.
+    pub fn with_compression(writer: W, method: CompressionMethod) -> Self {
+        Self {
+            writer: Some(ZipWriter::new(writer)),
+            finished: None,
+            options: SimpleFileOptions::default().compression_method(method),
+        }
+    }
+
+    /// Finishes the zip file if needed and returns the underlying writer.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.finish()?;
+        Ok(self.finished.take().expect("zip sink is finished"))
+    }
+
+    fn writer(&mut self) -> io::Result<&mut ZipWriter<W>> {
+        self.writer.as_mut().ok_or_else(|| io::Error::other("zip file has already been finished"))
+    }
+}
+
+impl<W: Write + Seek> ExtractSink for ZipSink<W> {
+    fn create_dir(&mut self, path: &Path) -> io::Result<()> {
+        let path = to_archive_path(path);
+        if path.is_empty() {
+            return Ok(());
+        }
+
+        let options = self.options;
+        self.writer()?.add_directory(path, options).map_err(io::Error::other)
+    }
+
+    fn write_file(&mut self, path: &Path, size: u64, data: &mut dyn Read) -> io::Result<u64> {
+        let options = self.options.large_file(size >= u32::MAX as u64);
+        let writer = self.writer()?;
+        writer.start_file(to_archive_path(path), options).map_err(io::Error::other)?;
+
+        io::copy(&mut ExactLengthReader::new(data, size), writer)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(writer) = self.writer.take() {
+            self.finished = Some(writer.finish().map_err(io::Error::other)?);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufWriter, Read, Seek},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    archive::SgaArchive,
+    builder::ArchiveBuilder,
+    options::ExtractOptions,
+    plan::sanitize_name,
+    subset::copy_stored,
+};
+
+/// How `split` divides an archive.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum SplitBy {
+    /// Every table of contents is written into its own archive.
+    #[default]
+    Toc,
+
+    /// Every top-level folder of a table of contents is written into its own archive, or only the given ones if any are.
+    /// The rest of the files of each table of contents, including those directly inside its root, are written into an archive of their own.
+    Folder(Vec<String>),
+}
+
+/// An archive written by `split`.
+#[derive(Debug, Clone)]
+pub struct SplitPart {
+    /// Path of the written archive.
+    pub path: PathBuf,
+
+    /// Alias of the table of contents the archive holds.
+    pub toc: String,
+
+    /// The top-level folder the archive holds, or `None` if it holds a whole table of contents or the rest of one.
+    pub folder: Option<String>,
+
+    /// Number of files in the archive.
+    pub files: usize,
+
+    /// Size of the data blob of the archive.
+    pub stored_bytes: u64,
+
+    /// Size of the stored data several files share, which is only stored once.
+    pub shared_bytes: u64,
+}
+
+/// Writes the tables of contents or top-level folders of `archive` into standalone archives in `folder`.
+///
+/// The archive of a table of contents is named after its alias, like `data.sga`, and the archive of a top-level folder
+/// after both, like `data-art.sga`. Files keep their paths, the folders are built again for each archive, and the stored data
+/// is copied as is. Merging every part gives back the files of `archive`.
+pub fn split<R>(archive: &mut SgaArchive<R>, folder: &Path, by: &SplitBy) -> Result<Vec<SplitPart>>
+where
+    R: Read + BufRead + Seek,
+{
+    let plan = archive.plan(&ExtractOptions::default())?;
+
+    // The builders of the parts, in the order their first file is found.
+    let mut parts: Vec<(String, Option<String>, ArchiveBuilder<usize>)> = Vec::new();
+    for planned in &plan.files {
+        let index = planned.file.index().with_context(|| format!("'{}' has no file entry", planned.archive_path))?;
+        let top_level = planned.archive_path.split_once('/').map(|(top_level, _)| top_level);
+        let part_folder = match (by, top_level) {
+            (SplitBy::Folder(chosen), Some(top_level))
+                if chosen.is_empty() || chosen.iter().any(|wanted| wanted.eq_ignore_ascii_case(top_level)) =>
+            {
+                Some(top_level.to_string())
+            }
+            _ => None,
+        };
+
+        let position = match parts.iter().position(|(toc, other, _)| *toc == planned.toc && *other == part_folder) {
+            Some(position) => position,
+            None => {
+                let toc_name = archive.toc(&planned.toc).map_or(planned.toc.as_str(), |toc| toc.name.as_str());
+                let mut builder = ArchiveBuilder::with_header(&archive.entries.header);
+                builder.add_toc(&planned.toc, toc_name);
+                parts.push((planned.toc.clone(), part_folder, builder));
+                parts.len() - 1
+            }
+        };
+
+        parts[position].2.add_file(&planned.toc, &planned.archive_path, index);
+    }
+
+    fs::create_dir_all(folder)?;
+    let mut written = Vec::with_capacity(parts.len());
+    for (toc, part_folder, builder) in parts {
+        let name = match &part_folder {
+            Some(part_folder) => format!("{}-{}.sga", toc, part_folder),
+            None => format!("{}.sga", toc),
+        };
+        let path = folder.join(sanitize_name(&name)?.as_ref());
+
+        let writer = BufWriter::new(File::create(&path).with_context(|| format!("Failed to create '{}'", path.display()))?);
+        let layout = builder
+            .write(writer, |&index| copy_stored(archive, index))
+            .with_context(|| format!("Failed to write '{}'", path.display()))?;
+
+        written.push(SplitPart {
+            path,
+            toc,
+            folder: part_folder,
+            files: layout.files.len(),
+            stored_bytes: layout.header.data_blob_length,
+            shared_bytes: layout.shared_bytes(),
+        });
+    }
+
+    Ok(written)
+}
@@ -0,0 +1,103 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    options::{ExtractOptions, VerificationLevel},
+    plan::ExtractPlan,
+};
+
+/// A hidden folder next to the output folder of an extraction, which the extraction is written into
+/// and which then replaces the output folder. It is removed if it's dropped before being committed.
+#[derive(Debug)]
+pub(crate) struct StagingDirectory {
+    path: PathBuf,
+    target: PathBuf,
+    committed: bool,
+}
+
+impl StagingDirectory {
+    /// Creates an empty staging folder for the output folder `target`.
+    pub(crate) fn create(target: &Path) -> Result<Self> {
+        let target = std::path::absolute(target)?;
+        let (Some(parent), Some(name)) = (target.parent(), target.file_name()) else {
+            bail!("The output folder '{}' can not be replaced atomically", target.display());
+        };
+
+        let path = parent.join(format!(".{}.staging-{}", name.to_string_lossy(), std::process::id()));
+        if path.exists() {
+            fs::remove_dir_all(&path)?;
+        }
+        fs::create_dir_all(&path).with_context(|| format!("Failed to create the staging folder '{}'", path.display()))?;
+
+        Ok(Self { path, target, committed: false })
+    }
+
+    /// The folder the extraction is written into.
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Checks that every planned file was written, with its decoded size unless verification is disabled.
+    pub(crate) fn verify(&self, plan: &ExtractPlan, options: &ExtractOptions) -> Result<()> {
+        for planned in &plan.files {
+            let path = self.path.join(&planned.path);
+            let metadata = fs::metadata(&path).with_context(|| format!("'{}' is missing from the staging folder", planned.path.display()))?;
+
+            let expected = planned.file.output_size(options.decoding_codecs()) as u64;
+            if options.verification != VerificationLevel::None && metadata.len() != expected {
+                bail!(
+                    "'{}' has {} bytes in the staging folder, but {} were expected",
+                    planned.path.display(),
+                    metadata.len(),
+                    expected
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the output folder with the staging folder.
+    ///
+    /// The previous output folder is first moved aside, so between the two renames the output folder is missing,
+    /// but it's never seen partially written.
+    pub(crate) fn commit(mut self) -> Result<()> {
+        let replaced = match self.target.exists() {
+            true => {
+                let name = self.target.file_name().unwrap_or_default().to_string_lossy();
+                let replaced = self.target.with_file_name(format!(".{}.replaced-{}", name, std::process::id()));
+                fs::rename(&self.target, &replaced)
+                    .with_context(|| format!("Failed to move '{}' aside", self.target.display()))?;
+                Some(replaced)
+            }
+            false => None,
+        };
+
+        if let Err(err) = fs::rename(&self.path, &self.target) {
+            if let Some(replaced) = &replaced {
+                let _ = fs::rename(replaced, &self.target);
+            }
+            return Err(err).with_context(|| format!("Failed to move the staging folder to '{}'", self.target.display()));
+        }
+
+        self.committed = true;
+        if let Some(replaced) = replaced {
+            fs::remove_dir_all(&replaced)
+                .with_context(|| format!("Failed to remove the previous output folder '{}'", replaced.display()))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for StagingDirectory {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
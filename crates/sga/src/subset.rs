@@ -0,0 +1,70 @@
+use std::io::{BufRead, Read, Seek, Write};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    archive::SgaArchive,
+    builder::{ArchiveBuilder, StoredFile},
+    options::ExtractOptions,
+};
+
+/// What `subset` wrote.
+#[derive(Debug, Clone, Default)]
+pub struct SubsetReport {
+    /// Number of tables of contents in the new archive.
+    pub tocs: usize,
+
+    /// Number of files in the new archive.
+    pub files: usize,
+
+    /// Size of the data blob of the new archive.
+    pub stored_bytes: u64,
+
+    /// Size of the stored data several files share, which is only stored once.
+    pub shared_bytes: u64,
+}
+
+/// Writes a new archive into `writer` holding the files of `archive` which `options` selects,
+/// at the same paths inside the same tables of contents.
+///
+/// The tables of contents, subtree, depth and include and exclude patterns of `options` select the files,
+/// the settings about the output are ignored. The stored data of every file is copied as is, the folders,
+/// string blob and hash blob are built again for the selected files, and tables of contents without any are left out.
+pub fn subset<R, W>(archive: &mut SgaArchive<R>, writer: W, options: &ExtractOptions) -> Result<SubsetReport>
+where
+    R: Read + BufRead + Seek,
+    W: Write + Seek,
+{
+    let plan = archive.plan(options)?;
+
+    let mut builder = ArchiveBuilder::with_header(&archive.entries.header);
+    for planned in &plan.files {
+        let index = planned.file.index().with_context(|| format!("'{}' has no file entry", planned.archive_path))?;
+        let toc_name = archive.toc(&planned.toc).map_or(planned.toc.as_str(), |toc| toc.name.as_str());
+
+        builder.add_toc(&planned.toc, toc_name);
+        builder.add_file(&planned.toc, &planned.archive_path, index);
+    }
+
+    let layout = builder.write(writer, |&index| copy_stored(archive, index))?;
+
+    Ok(SubsetReport {
+        tocs: layout.tocs.len(),
+        files: layout.files.len(),
+        stored_bytes: layout.header.data_blob_length,
+        shared_bytes: layout.shared_bytes(),
+    })
+}
+
+/// Reads the file entry at `index` of `archive`, to be copied as is into another archive.
+pub(crate) fn copy_stored<R: Read + BufRead + Seek>(archive: &mut SgaArchive<R>, index: usize) -> Result<StoredFile> {
+    let data = archive.read_stored(index)?;
+    let entry = &archive.entries.files[index];
+
+    Ok(StoredFile {
+        data,
+        storage_type: entry.storage_type,
+        uncompressed_size: entry.uncompressed_size,
+        verification_type: entry.verification_type,
+    })
+}
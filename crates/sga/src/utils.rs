@@ -1,4 +1,4 @@
-use std::{ffi::CString, io::{self, BufRead, ErrorKind, Read}};
+use std::{ffi::CString, io::{self, BufRead, ErrorKind, Read, Write}};
 use anyhow::Result;
 
 /// Reads a c string from the current position in the buffer.
@@ -30,12 +30,12 @@ pub fn read_fixed_string<R: Read>(reader: &mut R, char_count: usize, char_size:
 
     let string_bytes = &buffer[..effective_char_count * char_size];
 
-    let result = match char_size {
+    match char_size {
         1 => String::from_utf8(string_bytes.to_vec())
             .map_err(|_| io::Error::new(ErrorKind::InvalidData, "Invalid UTF-8")),
         2 => {
             use std::slice;
-            if string_bytes.len() % 2 != 0 {
+            if !string_bytes.len().is_multiple_of(2) {
                 return Err(io::Error::new(ErrorKind::InvalidData, "Odd number of bytes for UTF-16"));
             }
             let u16_slice: &[u16] = unsafe {
@@ -44,8 +44,45 @@ pub fn read_fixed_string<R: Read>(reader: &mut R, char_count: usize, char_size:
             String::from_utf16(u16_slice)
                 .map_err(|_| io::Error::new(ErrorKind::InvalidData, "Invalid UTF-16"))
         },
+        _ => Err(io::Error::new(ErrorKind::InvalidInput, "Unsupported char_size")),
+    }
+}
+
+/// Writes a fixed section into the buffer, padding `string` with zeros up to char_count chars.
+/// char_size is 1 for UTF-8 and 2 for UTF-16, like in `read_fixed_string`.
+pub fn write_fixed_string<W: Write>(writer: &mut W, string: &str, char_count: usize, char_size: usize) -> io::Result<()> {
+    let mut buffer = match char_size {
+        1 => string.as_bytes().to_vec(),
+        2 => string.encode_utf16().flat_map(u16::to_le_bytes).collect(),
         _ => return Err(io::Error::new(ErrorKind::InvalidInput, "Unsupported char_size")),
     };
 
-    result
-}
\ No newline at end of file
+    let total_bytes = char_count * char_size;
+    if buffer.len() > total_bytes {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("'{}' is longer than {} characters", string, char_count),
+        ));
+    }
+
+    buffer.resize(total_bytes, 0);
+    writer.write_all(&buffer)
+}
+
+/// Formats bytes as a lowercase hexadecimal string.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Parses a hexadecimal string written by `to_hex`.
+pub fn from_hex(hex: &str) -> io::Result<Vec<u8>> {
+    let invalid = || io::Error::new(ErrorKind::InvalidData, format!("Invalid hexadecimal string '{}'", hex));
+    if !hex.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok()).ok_or_else(invalid))
+        .collect()
+}
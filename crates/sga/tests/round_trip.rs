@@ -0,0 +1,1792 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    fs,
+    io::{self, Cursor, Read},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use sga::{
+    archive::{SgaArchive, TocLayout},
+    builder::StoredFile,
+    cancel::{CancellationToken, Cancelled},
+    codec::CodecRegistry,
+    conflict::{ConflictKind, ConflictResolution},
+    delta::{apply_delta, create_delta},
+    entires::{FileStorageType, FileVerificationType, SgaFileEntry, SgaFolderEntry, SgaHeader, SgaToC},
+    layout::ArchiveLayout,
+    manifest::ManifestFormat,
+    merge::{merge, Precedence},
+    observer::{ArchiveSummary, ExtractObserver},
+    pack::{repack_raw, write_archive},
+    packer::{pack_folder, PackConfig, PackOptions, PackRule},
+    patch::{compact, ArchivePatcher},
+    recompress::{recompress, StoragePolicy},
+    sink::{DirectorySink, ExtractSink, MemorySink, TarSink, ZipSink},
+    split::{split, SplitBy},
+    subset::subset,
+    AtomicMode, ExtractOptions, LinkMode, OverwritePolicy, VerificationLevel,
+};
+
+/// How a generated archive is laid out.
+#[derive(Clone, Copy)]
+struct Variant {
+    /// Files larger than this are stored zlib compressed.
+    compress_over: usize,
+
+    /// Zeros between the data of two files.
+    data_padding: u64,
+
+    /// Whether the header blob comes before the data blob.
+    header_blob_first: bool,
+
+    /// Whether files with the same contents share their data.
+    share_data: bool,
+
+    /// Size of the hash blob.
+    hash_length: u32,
+}
+
+struct Generated {
+    bytes: Vec<u8>,
+
+    /// The decoded contents of every file, keyed by `{alias}/{path}`.
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+/// The files of a table of contents, as `(path, contents)` with `/` separated paths.
+type TocFiles<'a> = &'a [(&'a str, &'a [u8])];
+
+/// The subfolders and files of a folder, keyed by its path separated by '\'.
+type FolderTree<'a> = BTreeMap<String, (Vec<String>, Vec<(&'a str, &'a [u8])>)>;
+
+const TOCS: &[(&str, TocFiles)] = &[
+    (
+        "data",
+        &[
+            ("top.txt", b"top level"),
+            ("art/readme.txt", b"readme readme readme readme readme readme readme readme"),
+            ("art/ui/icon.txt", b"icon icon icon icon icon icon icon icon icon icon icon"),
+            ("art/ui/copy.txt", b"icon icon icon icon icon icon icon icon icon icon icon"),
+            ("art/ui/deep/empty.bin", b""),
+            ("sound/voice.bin", &[7; 300]),
+        ],
+    ),
+    ("attrib", &[("a.rgd", b"attributes attributes attributes attributes"), ("b.txt", b"b")]),
+];
+
+/// Generates an archive holding `tocs`, laid out according to `variant`.
+fn generate(tocs: &[(&str, TocFiles)], variant: Variant) -> Generated {
+    let codecs = CodecRegistry::default();
+    let mut strings: Vec<u8> = Vec::new();
+    let mut add_string = |string: &str| {
+        let offset = strings.len() as u32;
+        strings.extend_from_slice(string.as_bytes());
+        strings.push(0);
+        offset
+    };
+
+    let mut toc_entries = Vec::new();
+    let mut folders = Vec::new();
+    let mut files = Vec::new();
+    let mut stored = Vec::new();
+    let mut expected = BTreeMap::new();
+    let mut data_blob_length = 0;
+    let mut shared_data: HashMap<Vec<u8>, (u64, usize)> = HashMap::new();
+
+    for (alias, toc_files) in tocs {
+        let mut tree = FolderTree::new();
+        tree.entry(String::new()).or_default();
+        for (path, data) in toc_files.iter() {
+            let (folder, name) = path.rsplit_once('/').unwrap_or(("", path));
+            let folder = folder.replace('/', "\\");
+            tree.entry(folder.clone()).or_default().1.push((name, data));
+            expected.insert(format!("{}/{}", alias, path), data.to_vec());
+
+            let mut child = folder;
+            while !child.is_empty() {
+                let parent = child.rsplit_once('\\').map_or("", |(parent, _)| parent).to_string();
+                let children = &mut tree.entry(parent.clone()).or_default().0;
+                if !children.contains(&child) {
+                    children.push(child.clone());
+                }
+                child = parent;
+            }
+        }
+
+        // Folders are numbered breadth first, so the subfolders of each folder are next to each other.
+        let mut order = Vec::new();
+        let mut queue = VecDeque::from([String::new()]);
+        while let Some(folder) = queue.pop_front() {
+            queue.extend(tree[&folder].0.iter().cloned());
+            order.push(folder);
+        }
+
+        let first_folder = folders.len() as u32;
+        let index_of = |folder: &str| first_folder + order.iter().position(|other| other == folder).unwrap() as u32;
+        let first_file = files.len() as u32;
+
+        for folder in &order {
+            let (children, folder_files) = &tree[folder];
+            let folder_start_index = children.first().map_or(0, |child| index_of(child));
+            let file_start_index = files.len() as u32;
+
+            for (name, data) in folder_files {
+                let name_offset = add_string(name);
+                let (storage_type, bytes) = match data.len() > variant.compress_over {
+                    true => {
+                        let codec = codecs.get(FileStorageType::BufferCompress).unwrap();
+                        (FileStorageType::BufferCompress, codec.encode(data, None).unwrap())
+                    }
+                    false => (FileStorageType::Store, data.to_vec()),
+                };
+
+                let data_offset = match shared_data.get(&bytes) {
+                    Some(&(offset, _)) if variant.share_data => offset,
+                    _ => {
+                        let offset = data_blob_length;
+                        data_blob_length += bytes.len() as u64 + variant.data_padding;
+                        shared_data.insert(bytes.clone(), (offset, files.len()));
+                        offset
+                    }
+                };
+
+                files.push(SgaFileEntry {
+                    name_offset,
+                    hash_offset: 0,
+                    data_offset,
+                    compressed_length: bytes.len() as u32,
+                    uncompressed_size: data.len() as u32,
+                    verification_type: FileVerificationType::CRC,
+                    storage_type,
+                    crc: crc32fast::hash(&bytes),
+                });
+                stored.push(bytes);
+            }
+
+            folders.push(SgaFolderEntry {
+                name_offset: add_string(folder),
+                folder_start_index,
+                folder_end_index: folder_start_index + children.len() as u32,
+                file_start_index,
+                file_end_index: files.len() as u32,
+            });
+        }
+
+        toc_entries.push(SgaToC {
+            alias: alias.to_string(),
+            name: alias.to_uppercase(),
+            folder_start_index: first_folder,
+            folder_end_index: folders.len() as u32,
+            file_start_index: first_file,
+            file_end_index: files.len() as u32,
+            folder_root_index: first_folder,
+        });
+    }
+
+    let toc_data_offset = SgaHeader::BLOB_INFO_SIZE;
+    let folder_data_offset = toc_data_offset + toc_entries.len() as u32 * SgaToC::SIZE;
+    let file_data_offset = folder_data_offset + folders.len() as u32 * SgaFolderEntry::SIZE;
+    let string_offset = file_data_offset + files.len() as u32 * SgaFileEntry::SIZE + 4;
+    let file_hash_offset = string_offset + strings.len() as u32;
+    let header_blob_length = file_hash_offset + variant.hash_length + 16;
+
+    let (header_blob_offset, data_offset) = match variant.header_blob_first {
+        true => (SgaHeader::SIZE, SgaHeader::SIZE + header_blob_length as u64 + 8),
+        false => (SgaHeader::SIZE + data_blob_length, SgaHeader::SIZE),
+    };
+
+    let layout = ArchiveLayout {
+        header: SgaHeader {
+            magic: *b"_ARCHIVE",
+            version: 10,
+            product: 0,
+            name: "generated".to_string(),
+            header_blob_offset,
+            header_blob_length,
+            data_offset,
+            data_blob_length,
+            unknown: 1,
+            toc_data_offset,
+            toc_data_count: toc_entries.len() as u32,
+            folder_data_offset,
+            folder_data_count: folders.len() as u32,
+            file_data_offset,
+            file_data_count: files.len() as u32,
+            string_offset,
+            string_length: strings.len() as u32,
+            block_size: 4096,
+            signature: std::array::from_fn(|i| (i * 7) as u8),
+            file_hash_offset,
+            file_hash_length: variant.hash_length,
+        },
+        tocs: toc_entries,
+        folders,
+        files,
+        strings: strings[..strings.len() - 1].split(|&byte| byte == 0).map(|s| String::from_utf8(s.to_vec()).unwrap()).collect(),
+        file_hashes: (0..variant.hash_length).map(|i| format!("{:02x}", i * 3 % 256)).collect(),
+    };
+
+    let mut bytes = Vec::new();
+    write_archive(&mut bytes, &layout, |index, _| Ok(stored[index].clone())).unwrap();
+
+    Generated { bytes, files: expected }
+}
+
+fn variants() -> Vec<Variant> {
+    vec![
+        Variant { compress_over: 40, data_padding: 0, header_blob_first: false, share_data: false, hash_length: 0 },
+        Variant { compress_over: usize::MAX, data_padding: 3, header_blob_first: true, share_data: true, hash_length: 32 },
+        Variant { compress_over: 0, data_padding: 16, header_blob_first: false, share_data: true, hash_length: 5 },
+    ]
+}
+
+/// Rewrites the archive in `bytes` with the changes `edit` makes to its layout, keeping the stored data of every file.
+fn edited(bytes: Vec<u8>, edit: impl FnOnce(&mut ArchiveLayout)) -> Vec<u8> {
+    let mut archive = SgaArchive::from_reader(Cursor::new(bytes)).unwrap();
+    let mut layout = archive.layout().unwrap();
+    edit(&mut layout);
+
+    let mut bytes = Vec::new();
+    write_archive(&mut bytes, &layout, |index, _| archive.read_stored(index)).unwrap();
+    bytes
+}
+
+/// Extracts `bytes` into a tar archive, one folder per table of contents.
+fn extract_to_tar(bytes: Vec<u8>, options: &ExtractOptions) -> anyhow::Result<Vec<u8>> {
+    let mut archive = SgaArchive::from_reader(Cursor::new(bytes))?;
+    let mut sink = TarSink::new(Vec::new());
+    archive.extract_to_sink(&mut sink, &options.clone().toc_layout(TocLayout::Subdirectory))?;
+    sink.finish()?;
+    Ok(sink.into_inner()?)
+}
+
+/// The contents of every file below `folder`, keyed by their path relative to it separated by `/`.
+fn read_tree(folder: &Path) -> BTreeMap<String, Vec<u8>> {
+    let mut files = BTreeMap::new();
+    let mut folders = vec![folder.to_path_buf()];
+    while let Some(current) = folders.pop() {
+        for entry in fs::read_dir(&current).unwrap() {
+            let path = entry.unwrap().path();
+            match path.is_dir() {
+                true => folders.push(path),
+                false => {
+                    let relative = path.strip_prefix(folder).unwrap().to_string_lossy().replace('\\', "/");
+                    files.insert(relative, fs::read(&path).unwrap());
+                }
+            }
+        }
+    }
+    files
+}
+
+/// The decoded contents of every file of the archive in `bytes`, keyed by `{alias}/{path}`.
+fn decoded(bytes: &[u8]) -> BTreeMap<String, Vec<u8>> {
+    let mut archive = SgaArchive::from_reader(Cursor::new(bytes.to_vec())).unwrap();
+    let mut sink = MemorySink::new();
+    let options = ExtractOptions::default().toc_layout(TocLayout::Subdirectory).verification(VerificationLevel::Crc);
+    archive.extract_to_sink(&mut sink, &options).unwrap();
+
+    sink.into_files()
+        .into_iter()
+        .map(|(path, data)| (path.to_string_lossy().replace('\\', "/"), data))
+        .collect()
+}
+
+/// The storage type of every file of the archive in `bytes`, keyed by `{alias}/{path}`.
+fn storage_types(bytes: &[u8]) -> BTreeMap<String, FileStorageType> {
+    let archive = SgaArchive::from_reader(Cursor::new(bytes.to_vec())).unwrap();
+    let plan = archive.plan(&ExtractOptions::default()).unwrap();
+
+    plan.files.iter().map(|file| (format!("{}/{}", file.toc, file.archive_path), file.file.storage_type)).collect()
+}
+
+/// The verification type of every file of the archive in `bytes`, keyed by `{alias}/{path}`.
+fn verification_types(bytes: &[u8]) -> BTreeMap<String, FileVerificationType> {
+    let archive = SgaArchive::from_reader(Cursor::new(bytes.to_vec())).unwrap();
+    let plan = archive.plan(&ExtractOptions::default()).unwrap();
+
+    plan.files
+        .iter()
+        .map(|file| (format!("{}/{}", file.toc, file.archive_path), file.file.entry().unwrap().verification_type))
+        .collect()
+}
+
+fn temp_folder(name: &str) -> PathBuf {
+    let folder = std::env::temp_dir().join(format!("sga-round-trip-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&folder);
+    folder
+}
+
+#[test]
+fn generated_archives_decode_to_their_files() {
+    for variant in variants() {
+        let generated = generate(TOCS, variant);
+        assert_eq!(decoded(&generated.bytes), generated.files);
+    }
+}
+
+/// A hand-written archive with two tables of contents, `data` holding `a.txt` with "hello" and `attrib` holding
+/// `b.txt` with "bye". Their data is stored right after the header, followed by the header blob.
+/// The unknown value of the header is 2 rather than the usual 1.
+fn fixture() -> Vec<u8> {
+    let mut bytes = Vec::new();
+
+    // Header
+    bytes.extend_from_slice(b"_ARCHIVE");
+    bytes.extend_from_slice(&10u16.to_le_bytes()); // version
+    bytes.extend_from_slice(&0u16.to_le_bytes()); // product
+    let name: Vec<u8> = "fixture".encode_utf16().flat_map(u16::to_le_bytes).collect();
+    bytes.extend_from_slice(&name);
+    bytes.resize(bytes.len() + 128 - name.len(), 0);
+    bytes.extend_from_slice(&436u64.to_le_bytes()); // header blob offset
+    bytes.extend_from_slice(&453u32.to_le_bytes()); // header blob length
+    bytes.extend_from_slice(&428u64.to_le_bytes()); // data offset
+    bytes.extend_from_slice(&8u64.to_le_bytes()); // data blob length
+    bytes.extend_from_slice(&2u32.to_le_bytes()); // unknown
+    bytes.extend((0..=255).map(|i: u8| i.wrapping_mul(5))); // signature
+    assert_eq!(bytes.len(), 428);
+
+    // Data blob
+    bytes.extend_from_slice(b"hellobye");
+
+    // Blob info: tocs, folders, files, strings, hashes and block size
+    for value in [44u32, 2, 340, 2, 380, 2, 440, 13, 453, 0, 4096] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    // Tables of contents, each with one root folder holding one file
+    for (index, (alias, name)) in [("data", "DATA"), ("attrib", "ATTRIB")].into_iter().enumerate() {
+        for string in [alias, name] {
+            bytes.extend_from_slice(string.as_bytes());
+            bytes.resize(bytes.len() + 64 - string.len(), 0);
+        }
+        let index = index as u32;
+        for value in [index, index + 1, index, index + 1, index] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    // Root folders, named by the empty string at offset 12
+    for index in 0..2u32 {
+        for value in [12u32, 0, 0, index, index + 1] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    // Files
+    for (name_offset, data_offset, length, crc) in [(0u32, 0u64, 5u32, 0x3610a686u32), (6, 5, 3, 0x77379134)] {
+        bytes.extend_from_slice(&name_offset.to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes()); // hash offset
+        bytes.extend_from_slice(&data_offset.to_le_bytes());
+        bytes.extend_from_slice(&length.to_le_bytes()); // compressed length
+        bytes.extend_from_slice(&length.to_le_bytes()); // uncompressed size
+        bytes.extend_from_slice(&[1, 0]); // CRC verification, stored
+        bytes.extend_from_slice(&crc.to_le_bytes()); // CRC32 of the data
+    }
+
+    // Strings
+    bytes.extend_from_slice(b"a.txt\0b.txt\0\0");
+    assert_eq!(bytes.len(), 436 + 453);
+
+    bytes
+}
+
+#[test]
+fn fixture_parses_to_its_exact_layout_and_writes_back_unchanged() {
+    let bytes = fixture();
+    let mut archive = SgaArchive::from_reader(Cursor::new(bytes.clone())).unwrap();
+    let layout = archive.layout().unwrap();
+
+    assert_eq!(
+        layout.header,
+        SgaHeader {
+            magic: *b"_ARCHIVE",
+            version: 10,
+            product: 0,
+            name: "fixture".to_string(),
+            header_blob_offset: 436,
+            header_blob_length: 453,
+            data_offset: 428,
+            data_blob_length: 8,
+            unknown: 2,
+            toc_data_offset: 44,
+            toc_data_count: 2,
+            folder_data_offset: 340,
+            folder_data_count: 2,
+            file_data_offset: 380,
+            file_data_count: 2,
+            string_offset: 440,
+            string_length: 13,
+            block_size: 4096,
+            signature: std::array::from_fn(|i| (i as u8).wrapping_mul(5)),
+            file_hash_offset: 453,
+            file_hash_length: 0,
+        }
+    );
+    assert_eq!(
+        layout.tocs,
+        vec![
+            SgaToC {
+                alias: "data".to_string(),
+                name: "DATA".to_string(),
+                folder_start_index: 0,
+                folder_end_index: 1,
+                file_start_index: 0,
+                file_end_index: 1,
+                folder_root_index: 0,
+            },
+            SgaToC {
+                alias: "attrib".to_string(),
+                name: "ATTRIB".to_string(),
+                folder_start_index: 1,
+                folder_end_index: 2,
+                file_start_index: 1,
+                file_end_index: 2,
+                folder_root_index: 1,
+            },
+        ]
+    );
+    assert_eq!(
+        layout.folders,
+        vec![
+            SgaFolderEntry { name_offset: 12, folder_start_index: 0, folder_end_index: 0, file_start_index: 0, file_end_index: 1 },
+            SgaFolderEntry { name_offset: 12, folder_start_index: 0, folder_end_index: 0, file_start_index: 1, file_end_index: 2 },
+        ]
+    );
+    assert_eq!(
+        layout.files,
+        vec![
+            SgaFileEntry {
+                name_offset: 0,
+                hash_offset: 0,
+                data_offset: 0,
+                compressed_length: 5,
+                uncompressed_size: 5,
+                verification_type: FileVerificationType::CRC,
+                storage_type: FileStorageType::Store,
+                crc: 0x3610a686,
+            },
+            SgaFileEntry {
+                name_offset: 6,
+                hash_offset: 0,
+                data_offset: 5,
+                compressed_length: 3,
+                uncompressed_size: 3,
+                verification_type: FileVerificationType::CRC,
+                storage_type: FileStorageType::Store,
+                crc: 0x77379134,
+            },
+        ]
+    );
+    assert_eq!(layout.strings, vec!["a.txt", "b.txt", ""]);
+
+    let mut sink = MemorySink::new();
+    let options = ExtractOptions::default().toc_layout(TocLayout::Subdirectory).verification(VerificationLevel::Crc);
+    archive.extract_to_sink(&mut sink, &options).unwrap();
+    assert_eq!(
+        sink.into_files(),
+        HashMap::from([(PathBuf::from("data/a.txt"), b"hello".to_vec()), (PathBuf::from("attrib/b.txt"), b"bye".to_vec())])
+    );
+
+    let mut written = Vec::new();
+    write_archive(&mut written, &layout, |index, _| archive.read_stored(index)).unwrap();
+    assert_eq!(written, bytes);
+}
+
+#[test]
+fn tables_of_contents_are_extracted_into_folders_named_after_their_alias() {
+    let mut archive = SgaArchive::from_reader(Cursor::new(fixture())).unwrap();
+    let aliases: Vec<_> = archive.tocs().iter().map(|toc| toc.alias.as_str()).collect();
+    assert_eq!(aliases, ["data", "attrib"]);
+
+    let folder = temp_folder("tocs");
+    archive.extract(&ExtractOptions::new(&folder)).unwrap();
+    let every_toc = read_tree(&folder);
+    fs::remove_dir_all(&folder).unwrap();
+
+    // A table of contents is selected by its alias or its name.
+    let mut selected = Vec::new();
+    for toc in ["attrib", "ATTRIB"] {
+        archive.extract(&ExtractOptions::new(&folder).toc(toc)).unwrap();
+        selected.push(read_tree(&folder));
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    let expected = BTreeMap::from([("data/a.txt".to_string(), b"hello".to_vec()), ("attrib/b.txt".to_string(), b"bye".to_vec())]);
+    assert_eq!(every_toc, expected);
+    let attrib = BTreeMap::from([("attrib/b.txt".to_string(), b"bye".to_vec())]);
+    assert_eq!(selected, [attrib.clone(), attrib]);
+}
+
+/// Collects the paths of the warnings of an extraction.
+#[derive(Default)]
+struct Warnings(Mutex<Vec<String>>);
+
+impl ExtractObserver for Warnings {
+    fn warning(&self, path: Option<&Path>, _message: &str) {
+        let path = path.map_or(String::new(), |path| path.to_string_lossy().replace('\\', "/"));
+        self.0.lock().unwrap().push(path);
+    }
+}
+
+/// An event of an extraction, with paths separated by `/`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Event {
+    Opened { toc_count: usize, file_count: usize, total_bytes: u64 },
+    Folder(String),
+    Started(String, u64),
+    Finished(String, u64),
+    Error(Option<String>),
+}
+
+/// Records every event of an extraction, in order.
+#[derive(Default)]
+struct Recorder(Mutex<Vec<Event>>);
+
+impl Recorder {
+    fn events(&self) -> Vec<Event> {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn push(&self, event: Event) {
+        self.0.lock().unwrap().push(event);
+    }
+}
+
+fn slashed(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+impl ExtractObserver for Recorder {
+    fn archive_opened(&self, archive: &ArchiveSummary) {
+        self.push(Event::Opened { toc_count: archive.toc_count, file_count: archive.file_count, total_bytes: archive.total_bytes });
+    }
+
+    fn folder_entered(&self, path: &Path) {
+        self.push(Event::Folder(slashed(path)));
+    }
+
+    fn file_started(&self, path: &Path, size: u64) {
+        self.push(Event::Started(slashed(path), size));
+    }
+
+    fn file_finished(&self, path: &Path, bytes_written: u64) {
+        self.push(Event::Finished(slashed(path), bytes_written));
+    }
+
+    fn error(&self, path: Option<&Path>, _error: &anyhow::Error) {
+        self.push(Event::Error(path.map(slashed)));
+    }
+}
+
+#[test]
+fn observers_see_the_archive_then_every_folder_then_each_file_start_and_finish() {
+    let generated = generate(TOCS, variants()[0]);
+    let recorder = Arc::new(Recorder::default());
+
+    let mut archive = SgaArchive::from_reader(Cursor::new(generated.bytes)).unwrap();
+    let options = ExtractOptions::default().toc_layout(TocLayout::Subdirectory).observer(recorder.clone());
+    let report = archive.extract_to_sink(&mut MemorySink::new(), &options).unwrap();
+
+    let mut expected = vec![Event::Opened {
+        toc_count: TOCS.len(),
+        file_count: generated.files.len(),
+        total_bytes: generated.files.values().map(|data| data.len() as u64).sum(),
+    }];
+    expected.extend(report.plan.folders.iter().map(|path| Event::Folder(slashed(path))));
+    for planned in &report.plan.files {
+        let path = slashed(&planned.path);
+        let size = generated.files[&path].len() as u64;
+        expected.extend([Event::Started(path.clone(), size), Event::Finished(path, size)]);
+    }
+    assert_eq!(recorder.events(), expected);
+    assert_eq!(report.plan.files.len(), generated.files.len());
+}
+
+#[test]
+fn observers_see_the_error_which_stops_an_extraction() {
+    let generated = generate(TOCS, variants()[0]);
+    let bytes = edited(generated.bytes, |layout| layout.files[0].crc ^= 1);
+    let recorder = Arc::new(Recorder::default());
+
+    let mut archive = SgaArchive::from_reader(Cursor::new(bytes)).unwrap();
+    let options = ExtractOptions::default().verification(VerificationLevel::Crc).observer(recorder.clone());
+    assert!(archive.extract_to_sink(&mut MemorySink::new(), &options).is_err());
+
+    let events = recorder.events();
+    let Some(Event::Started(path, _)) = events.iter().rev().nth(1) else {
+        panic!("the failing file didn't start right before the error: {:?}", events);
+    };
+    assert_eq!(events.last(), Some(&Event::Error(Some(path.clone()))));
+    assert!(!events.iter().any(|event| matches!(event, Event::Finished(finished, _) if finished == path)));
+}
+
+#[test]
+fn unknown_storage_types_are_reported_to_the_observer() {
+    let generated = generate(TOCS, variants()[0]);
+    let mut codecs = CodecRegistry::default();
+    codecs.unregister(FileStorageType::BufferCompress);
+
+    let warnings = Arc::new(Warnings::default());
+    let mut archive = SgaArchive::from_reader(Cursor::new(generated.bytes)).unwrap();
+    let mut sink = MemorySink::new();
+    let options = ExtractOptions::default().toc_layout(TocLayout::Subdirectory).codecs(codecs).observer(warnings.clone());
+    archive.extract_to_sink(&mut sink, &options).unwrap();
+
+    let mut warned = warnings.0.lock().unwrap().clone();
+    warned.sort();
+    let compressed: Vec<String> = generated.files.iter().filter(|(_, data)| data.len() > 40).map(|(path, _)| path.clone()).collect();
+    assert_eq!(warned, compressed);
+    assert_eq!(sink.into_files().len(), generated.files.len());
+}
+
+#[test]
+fn raw_extraction_repacks_byte_for_byte() {
+    for (i, variant) in variants().into_iter().enumerate() {
+        for format in [ManifestFormat::Json, ManifestFormat::Toml] {
+            let generated = generate(TOCS, variant);
+            let folder = temp_folder(&format!("{}-{:?}", i, format));
+            let repacked = folder.with_extension("sga");
+
+            let mut archive = SgaArchive::from_reader(Cursor::new(generated.bytes.clone())).unwrap();
+            let options = ExtractOptions::new(&folder).raw(true).manifest(format).verification(VerificationLevel::Crc);
+            archive.extract(&options).unwrap();
+
+            repack_raw(&folder, &repacked).unwrap();
+            let bytes = fs::read(&repacked).unwrap();
+
+            fs::remove_dir_all(&folder).unwrap();
+            fs::remove_file(&repacked).unwrap();
+            assert!(bytes == generated.bytes, "variant {} with a {:?} manifest was not repacked byte for byte", i, format);
+        }
+    }
+}
+
+#[test]
+fn repacking_needs_every_file() {
+    let generated = generate(TOCS, variants()[0]);
+    let folder = temp_folder("partial");
+    let repacked = folder.with_extension("sga");
+
+    let mut archive = SgaArchive::from_reader(Cursor::new(generated.bytes)).unwrap();
+    archive
+        .extract(&ExtractOptions::new(&folder).raw(true).manifest(ManifestFormat::Json).toc("attrib"))
+        .unwrap();
+
+    let result = repack_raw(&folder, &repacked);
+    fs::remove_dir_all(&folder).unwrap();
+    let _ = fs::remove_file(&repacked);
+    assert!(result.is_err());
+}
+
+/// Creates `files` inside `folder` in the order they're given, each inside the folder of its table of contents.
+fn write_tree(folder: &Path, files: &[(&str, &str, &[u8])]) {
+    for (alias, path, data) in files {
+        let path = folder.join(alias).join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+}
+
+#[test]
+fn packing_is_deterministic() {
+    let mut files: Vec<(&str, &str, &[u8])> =
+        TOCS.iter().flat_map(|(alias, files)| files.iter().map(move |(path, data)| (*alias, *path, *data))).collect();
+    let options = PackOptions::new("packed").storage_type(FileStorageType::BufferCompress);
+    let parallel = options.clone().jobs(3).buffer_size(64);
+
+    let mut packed = Vec::new();
+    for (i, name) in ["forward", "backward"].into_iter().enumerate() {
+        if i == 1 {
+            files.reverse();
+        }
+        let folder = temp_folder(name);
+        write_tree(&folder, &files);
+
+        for options in [&options, &parallel] {
+            let mut bytes = Cursor::new(Vec::new());
+            let report = pack_folder(&folder, &mut bytes, options);
+            assert_eq!(report.unwrap().files, files.len());
+            packed.push(bytes.into_inner());
+        }
+        fs::remove_dir_all(&folder).unwrap();
+    }
+    assert!(packed[0] == packed[1], "packing the same files on several threads gave another archive");
+    assert!(packed[0] == packed[2], "packing the same files created in another order gave another archive");
+    assert!(packed[2] == packed[3], "packing the same files on several threads gave another archive");
+
+    let mut archive = SgaArchive::from_reader(Cursor::new(packed.remove(0))).unwrap();
+    let mut sink = MemorySink::new();
+    let options = ExtractOptions::default().toc_layout(TocLayout::Subdirectory).verification(VerificationLevel::Crc);
+    archive.extract_to_sink(&mut sink, &options).unwrap();
+
+    let extracted: BTreeMap<String, Vec<u8>> = sink
+        .into_files()
+        .into_iter()
+        .map(|(path, data)| (path.to_string_lossy().replace('\\', "/"), data))
+        .collect();
+    let expected: BTreeMap<String, Vec<u8>> =
+        files.iter().map(|(alias, path, data)| (format!("{}/{}", alias, path), data.to_vec())).collect();
+    assert_eq!(extracted, expected);
+}
+
+#[test]
+fn tar_output_reads_back() {
+    for variant in variants() {
+        let generated = generate(TOCS, variant);
+        let tar = extract_to_tar(generated.bytes, &ExtractOptions::default()).unwrap();
+
+        let mut files = BTreeMap::new();
+        for entry in tar::Archive::new(Cursor::new(tar)).entries().unwrap() {
+            let mut entry = entry.unwrap();
+            if entry.header().entry_type() == tar::EntryType::Regular {
+                let path = entry.path().unwrap().to_string_lossy().into_owned();
+                let mut data = Vec::new();
+                std::io::Read::read_to_end(&mut entry, &mut data).unwrap();
+                files.insert(path, data);
+            }
+        }
+        assert_eq!(files, generated.files);
+    }
+}
+
+#[test]
+fn sinks_reject_data_of_the_wrong_length() {
+    let generated = generate(TOCS, variants()[0]);
+    let bytes = edited(generated.bytes, |layout| {
+        let entry = layout.files.iter_mut().find(|entry| entry.uncompressed_size == 1).unwrap();
+        entry.uncompressed_size = 5;
+    });
+    let options = ExtractOptions::default().verification(VerificationLevel::None);
+
+    assert!(extract_to_tar(bytes.clone(), &options).is_err());
+
+    let mut archive = SgaArchive::from_reader(Cursor::new(bytes.clone())).unwrap();
+    assert!(archive.extract_to_sink(&mut ZipSink::new(Cursor::new(Vec::new())), &options).is_err());
+
+    let mut archive = SgaArchive::from_reader(Cursor::new(bytes)).unwrap();
+    assert!(archive.extract_to_sink(&mut MemorySink::new(), &options).is_err());
+}
+
+#[test]
+fn overwrite_policies_apply_to_existing_files() {
+    let generated = generate(TOCS, variants()[0]);
+    let existing: BTreeMap<String, Vec<u8>> =
+        [("data/top.txt", b"existing top" as &[u8]), ("attrib/b.txt", b"existing b")].map(|(path, data)| (path.to_string(), data.to_vec())).into();
+
+    for policy in [OverwritePolicy::Overwrite, OverwritePolicy::Skip, OverwritePolicy::Rename, OverwritePolicy::Error] {
+        let folder = temp_folder(&format!("{:?}", policy));
+        for (path, data) in &existing {
+            fs::create_dir_all(folder.join(path).parent().unwrap()).unwrap();
+            fs::write(folder.join(path), data).unwrap();
+        }
+
+        let mut archive = SgaArchive::from_reader(Cursor::new(generated.bytes.clone())).unwrap();
+        let result = archive.extract(&ExtractOptions::new(&folder).toc_layout(TocLayout::Subdirectory).overwrite(policy));
+        let files = read_tree(&folder);
+        fs::remove_dir_all(&folder).unwrap();
+
+        let mut expected = generated.files.clone();
+        match policy {
+            OverwritePolicy::Overwrite => {}
+            OverwritePolicy::Skip => expected.extend(existing.clone()),
+            OverwritePolicy::Rename => {
+                expected.insert("data/top (1).txt".to_string(), generated.files["data/top.txt"].clone());
+                expected.insert("attrib/b (1).txt".to_string(), generated.files["attrib/b.txt"].clone());
+                expected.extend(existing.clone());
+            }
+            OverwritePolicy::Error => {
+                assert!(result.is_err());
+                assert_eq!(files, existing);
+                continue;
+            }
+        }
+        assert_eq!(files, expected, "{:?}", policy);
+
+        let conflicts = result.unwrap().conflicts;
+        assert_eq!(conflicts.len(), existing.len());
+        for conflict in conflicts {
+            assert_eq!(conflict.kind, ConflictKind::Existing);
+            let resolution = match policy {
+                OverwritePolicy::Skip => ConflictResolution::Skipped,
+                OverwritePolicy::Rename => ConflictResolution::Renamed(conflict.path.with_file_name(format!(
+                    "{} (1).txt",
+                    conflict.path.file_stem().unwrap().to_string_lossy()
+                ))),
+                _ => ConflictResolution::Overwritten,
+            };
+            assert_eq!(conflict.resolution, resolution);
+        }
+    }
+}
+
+/// Cancels an extraction once it has finished a number of files.
+struct CancelAfter {
+    token: CancellationToken,
+    remaining: Mutex<usize>,
+}
+
+impl ExtractObserver for CancelAfter {
+    fn file_finished(&self, _path: &Path, _bytes_written: u64) {
+        let mut remaining = self.remaining.lock().unwrap();
+        *remaining = remaining.saturating_sub(1);
+        if *remaining == 0 {
+            self.token.cancel();
+        }
+    }
+}
+
+/// Extracts `bytes` into `folder`, cancelling the extraction after `files` files.
+fn extract_interrupted(bytes: Vec<u8>, folder: &Path, files: usize) {
+    let token = CancellationToken::new();
+    let observer = Arc::new(CancelAfter { token: token.clone(), remaining: Mutex::new(files) });
+    let options = ExtractOptions::new(folder).toc_layout(TocLayout::Subdirectory).cancellation(token).observer(observer);
+
+    let result = SgaArchive::from_reader(Cursor::new(bytes)).unwrap().extract(&options);
+    assert!(Cancelled::is_cause_of(&result.unwrap_err()));
+}
+
+#[test]
+fn resuming_skips_completed_files() {
+    let generated = generate(TOCS, variants()[0]);
+    let folder = temp_folder("resume");
+    extract_interrupted(generated.bytes.clone(), &folder, 3);
+
+    // A completed file which is changed on disk stays changed, since it isn't extracted again.
+    let interrupted = read_tree(&folder);
+    let (changed, _) = interrupted.iter().find(|(path, _)| generated.files.contains_key(*path)).unwrap();
+    fs::write(folder.join(changed), b"changed").unwrap();
+
+    let options = ExtractOptions::new(&folder).toc_layout(TocLayout::Subdirectory).resume(true);
+    let report = SgaArchive::from_reader(Cursor::new(generated.bytes.clone())).unwrap().extract(&options).unwrap();
+    let files = read_tree(&folder);
+    fs::remove_dir_all(&folder).unwrap();
+
+    assert_eq!(report.resumed, 3);
+    assert_eq!(report.plan.files.len(), generated.files.len() - 3);
+    let mut expected = generated.files.clone();
+    expected.insert(changed.clone(), b"changed".to_vec());
+    assert_eq!(files, expected);
+}
+
+#[test]
+fn resuming_ignores_the_journal_of_another_archive() {
+    let generated = generate(TOCS, variants()[0]);
+    let other = generate(TOCS, variants()[1]);
+    let folder = temp_folder("resume-other");
+    extract_interrupted(other.bytes, &folder, 3);
+
+    let options = ExtractOptions::new(&folder).toc_layout(TocLayout::Subdirectory).resume(true);
+    let report = SgaArchive::from_reader(Cursor::new(generated.bytes)).unwrap().extract(&options).unwrap();
+    let files = read_tree(&folder);
+    fs::remove_dir_all(&folder).unwrap();
+
+    assert_eq!(report.resumed, 0);
+    assert_eq!(report.plan.files.len(), generated.files.len());
+    assert_eq!(files, generated.files);
+}
+
+#[test]
+fn failing_before_extracting_leaves_no_journal() {
+    let generated = generate(TOCS, variants()[0]);
+    let folder = temp_folder("no-journal");
+
+    let options = ExtractOptions::new(&folder).toc("missing");
+    assert!(SgaArchive::from_reader(Cursor::new(generated.bytes)).unwrap().extract(&options).is_err());
+    assert!(!folder.exists());
+}
+
+#[test]
+fn names_from_the_archive_stay_inside_the_output() {
+    for evil in ["../evil", "..\\evil"] {
+        let generated = generate(TOCS, variants()[0]);
+        let bytes = edited(generated.bytes, |layout| {
+            let name = layout.strings.iter_mut().find(|string| *string == "top.txt").unwrap();
+            *name = evil.to_string();
+        });
+
+        let parent = temp_folder("traversal");
+        let folder = parent.join("output");
+        let options = ExtractOptions::new(&folder).toc_layout(TocLayout::Subdirectory);
+        SgaArchive::from_reader(Cursor::new(bytes)).unwrap().extract(&options).unwrap();
+
+        let outside = read_tree(&parent);
+        let inside = read_tree(&folder);
+        fs::remove_dir_all(&parent).unwrap();
+
+        assert_eq!(outside.len(), inside.len(), "a file was written outside of the output");
+        assert_eq!(inside["data/.._evil"], generated.files["data/top.txt"]);
+    }
+}
+
+#[test]
+fn recompressing_changes_the_storage_type_and_keeps_the_contents() {
+    for variant in variants() {
+        let generated = generate(TOCS, variant);
+
+        for storage_type in [FileStorageType::Store, FileStorageType::BufferCompress, FileStorageType::BufferCompressBrotli] {
+            let mut archive = SgaArchive::from_reader(Cursor::new(generated.bytes.clone())).unwrap();
+            let mut output = Cursor::new(Vec::new());
+            recompress(&mut archive, &mut output, &StoragePolicy::new(storage_type)).unwrap();
+
+            let recompressed = output.into_inner();
+            assert!(storage_types(&recompressed).values().all(|&other| other == storage_type));
+            assert_eq!(decoded(&recompressed), generated.files);
+        }
+    }
+}
+
+#[test]
+fn recompressing_applies_extension_rules_before_size_rules() {
+    let generated = generate(TOCS, variants()[0]);
+    let policy = StoragePolicy::new(FileStorageType::BufferCompress)
+        .extension("BIN", FileStorageType::Store)
+        .smaller_than(20, FileStorageType::BufferCompressBrotli);
+
+    let mut archive = SgaArchive::from_reader(Cursor::new(generated.bytes)).unwrap();
+    let mut output = Cursor::new(Vec::new());
+    recompress(&mut archive, &mut output, &policy).unwrap();
+
+    let recompressed = output.into_inner();
+    let expected: BTreeMap<String, FileStorageType> = generated
+        .files
+        .iter()
+        .map(|(path, data)| {
+            let storage_type = if path.ends_with(".bin") {
+                FileStorageType::Store
+            } else if data.len() < 20 {
+                FileStorageType::BufferCompressBrotli
+            } else {
+                FileStorageType::BufferCompress
+            };
+            (path.clone(), storage_type)
+        })
+        .collect();
+    assert_eq!(storage_types(&recompressed), expected);
+    assert_eq!(decoded(&recompressed), generated.files);
+}
+
+#[test]
+fn recompressing_keeps_storage_types_without_rules() {
+    let generated = generate(TOCS, variants()[0]);
+
+    let mut archive = SgaArchive::from_reader(Cursor::new(generated.bytes.clone())).unwrap();
+    let mut output = Cursor::new(Vec::new());
+    let report = recompress(&mut archive, &mut output, &StoragePolicy::keep()).unwrap();
+
+    let recompressed = output.into_inner();
+    assert_eq!(report.reencoded, 0);
+    assert_eq!(storage_types(&recompressed), storage_types(&generated.bytes));
+    assert_eq!(decoded(&recompressed), generated.files);
+}
+
+#[test]
+fn subsets_hold_exactly_the_selected_files() {
+    let cases: [(ExtractOptions, &[&str]); 3] = [
+        (
+            ExtractOptions::default().include("art/**").exclude("**/COPY.TXT"),
+            &["data/art/readme.txt", "data/art/ui/deep/empty.bin", "data/art/ui/icon.txt"],
+        ),
+        (ExtractOptions::default().include("*.txt").toc("attrib"), &["attrib/b.txt"]),
+        (ExtractOptions::default().exclude("*.txt"), &["attrib/a.rgd", "data/art/ui/deep/empty.bin", "data/sound/voice.bin"]),
+    ];
+
+    for variant in variants() {
+        let generated = generate(TOCS, variant);
+        let original_storage_types = storage_types(&generated.bytes);
+
+        for (options, expected) in &cases {
+            let mut archive = SgaArchive::from_reader(Cursor::new(generated.bytes.clone())).unwrap();
+            let mut output = Cursor::new(Vec::new());
+            let report = subset(&mut archive, &mut output, options).unwrap();
+
+            let subset = output.into_inner();
+            let files = decoded(&subset);
+            assert_eq!(files.keys().collect::<Vec<_>>(), *expected);
+            assert!(files.iter().all(|(path, data)| generated.files[path] == *data));
+            assert!(storage_types(&subset).iter().all(|(path, storage_type)| original_storage_types[path] == *storage_type));
+
+            let tocs: BTreeSet<&str> = expected.iter().map(|path| path.split('/').next().unwrap()).collect();
+            assert_eq!(report.tocs, tocs.len());
+            assert_eq!(report.files, expected.len());
+        }
+    }
+}
+
+/// Files overriding some of `TOCS`, one of them named in another case, and adding others.
+const OVERRIDES: &[(&str, TocFiles)] = &[
+    ("data", &[("TOP.TXT", b"overridden top"), ("art/readme.txt", b"overridden readme"), ("mods/new.txt", b"new")]),
+    ("extra", &[("x.txt", b"x")]),
+];
+
+#[test]
+fn merging_keeps_the_file_of_the_archive_with_precedence() {
+    let base = generate(TOCS, variants()[0]);
+    let overrides = generate(OVERRIDES, variants()[2]);
+
+    for precedence in [Precedence::Last, Precedence::First] {
+        let mut archives = [&base, &overrides].map(|generated| SgaArchive::from_reader(Cursor::new(generated.bytes.clone())).unwrap());
+        let mut output = Cursor::new(Vec::new());
+        let report = merge(&mut archives, &mut output, precedence).unwrap();
+
+        let (kept, replaced) = match precedence {
+            Precedence::Last => (&overrides, &base),
+            Precedence::First => (&base, &overrides),
+        };
+        let mut expected = replaced.files.clone();
+        expected.retain(|path, _| !kept.files.keys().any(|other| other.eq_ignore_ascii_case(path)));
+        expected.extend(kept.files.clone());
+        assert_eq!(decoded(&output.into_inner()), expected);
+
+        let mut overridden: Vec<(String, String, usize, usize)> = report
+            .overrides
+            .into_iter()
+            .map(|other| (other.toc, other.path, other.kept, other.replaced))
+            .collect();
+        overridden.sort();
+        let expected_overrides = match precedence {
+            Precedence::Last => [("data", "TOP.TXT", 1, 0), ("data", "art/readme.txt", 1, 0)],
+            Precedence::First => [("data", "art/readme.txt", 0, 1), ("data", "top.txt", 0, 1)],
+        };
+        assert_eq!(overridden, expected_overrides.map(|(toc, path, kept, replaced)| (toc.to_string(), path.to_string(), kept, replaced)));
+        assert_eq!(report.tocs, 3);
+        assert_eq!(report.files, expected.len());
+    }
+}
+
+/// The name of every archive `split` writes, with the prefixes of the paths of the files it holds.
+type Parts<'a> = &'a [(&'a str, &'a [&'a str])];
+
+#[test]
+fn split_parts_hold_only_their_folder() {
+    let cases: [(SplitBy, Parts); 3] = [
+        (SplitBy::Toc, &[("attrib.sga", &["attrib/"]), ("data.sga", &["data/"])]),
+        (
+            SplitBy::Folder(Vec::new()),
+            &[
+                ("attrib.sga", &["attrib/"]),
+                ("data-art.sga", &["data/art/"]),
+                ("data-sound.sga", &["data/sound/"]),
+                ("data.sga", &["data/top.txt"]),
+            ],
+        ),
+        (
+            SplitBy::Folder(vec!["ART".to_string()]),
+            &[("attrib.sga", &["attrib/"]), ("data-art.sga", &["data/art/"]), ("data.sga", &["data/sound/", "data/top.txt"])],
+        ),
+    ];
+
+    for (i, variant) in variants().into_iter().enumerate() {
+        let generated = generate(TOCS, variant);
+
+        for (by, parts) in &cases {
+            let folder = temp_folder(&format!("split-{}", i));
+            let mut archive = SgaArchive::from_reader(Cursor::new(generated.bytes.clone())).unwrap();
+            let written = split(&mut archive, &folder, by).unwrap();
+
+            let mut names: Vec<String> = written.iter().map(|part| part.path.file_name().unwrap().to_string_lossy().into_owned()).collect();
+            names.sort();
+            assert_eq!(names, parts.iter().map(|(name, _)| *name).collect::<Vec<_>>());
+
+            for (name, prefixes) in parts.iter() {
+                let mut expected = generated.files.clone();
+                expected.retain(|path, _| prefixes.iter().any(|prefix| path.starts_with(prefix)));
+                assert_eq!(decoded(&fs::read(folder.join(name)).unwrap()), expected, "{}", name);
+            }
+
+            fs::remove_dir_all(&folder).unwrap();
+        }
+    }
+}
+
+/// The size of the data blob of the archive in `bytes`.
+fn data_blob_length(bytes: &[u8]) -> u64 {
+    SgaArchive::from_reader(Cursor::new(bytes.to_vec())).unwrap().layout().unwrap().header.data_blob_length
+}
+
+#[test]
+fn patching_replaces_adds_and_removes_files_and_compacting_reclaims_their_data() {
+    let codecs = CodecRegistry::default();
+    let encode = |data: &[u8], storage_type| StoredFile::encode(data, storage_type, FileVerificationType::CRC, &codecs, None).unwrap();
+
+    for (i, variant) in variants().into_iter().enumerate() {
+        let generated = generate(TOCS, variant);
+        let folder = temp_folder(&format!("patch-{}", i));
+        fs::create_dir_all(&folder).unwrap();
+        let path = folder.join("archive.sga");
+        fs::write(&path, &generated.bytes).unwrap();
+
+        let mut patcher = ArchivePatcher::open(&path).unwrap();
+        let replacement = [9; 200];
+        patcher.replace("data", "ART/readme.txt", &encode(&replacement, FileStorageType::BufferCompress)).unwrap();
+        patcher.add("attrib", "new/c.txt", &encode(b"added", FileStorageType::Store)).unwrap();
+        patcher.remove("data", "sound/voice.bin").unwrap();
+        assert!(patcher.add("data", "TOP.TXT", &encode(b"twice", FileStorageType::Store)).is_err());
+        assert!(patcher.replace("data", "missing.txt", &encode(b"missing", FileStorageType::Store)).is_err());
+        assert!(patcher.remove("missing", "top.txt").is_err());
+        let report = patcher.commit().unwrap();
+        assert_eq!((report.replaced, report.added, report.removed), (1, 1, 1));
+        assert!(report.dead_bytes > 0);
+
+        let mut expected = generated.files.clone();
+        expected.insert("data/art/readme.txt".to_string(), replacement.to_vec());
+        expected.insert("attrib/new/c.txt".to_string(), b"added".to_vec());
+        expected.remove("data/sound/voice.bin");
+        let patched = fs::read(&path).unwrap();
+        assert_eq!(decoded(&patched), expected);
+
+        let compacted_report = compact(&path).unwrap();
+        let compacted = fs::read(&path).unwrap();
+        assert_eq!(compacted_report.bytes_before, patched.len() as u64);
+        assert_eq!(compacted_report.bytes_after, compacted.len() as u64);
+        assert!(compacted.len() < patched.len());
+        assert!(data_blob_length(&compacted) < data_blob_length(&patched));
+        assert_eq!(decoded(&compacted), expected);
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
+
+/// `TOCS` in a later version, with a file changed, one added and two removed.
+const UPDATED: &[(&str, TocFiles)] = &[
+    (
+        "data",
+        &[
+            ("top.txt", b"top level, changed"),
+            ("art/readme.txt", b"readme readme readme readme readme readme readme readme"),
+            ("art/new.txt", b"new new new"),
+            ("art/ui/icon.txt", b"icon icon icon icon icon icon icon icon icon icon icon"),
+            ("art/ui/deep/empty.bin", b""),
+            ("sound/voice.bin", &[7; 300]),
+        ],
+    ),
+    ("attrib", &[("a.rgd", b"attributes attributes attributes attributes")]),
+];
+
+#[test]
+fn applying_a_delta_rebuilds_its_target() {
+    for variant in variants() {
+        let old = generate(TOCS, variant);
+        let updated = generate(UPDATED, variant);
+
+        // Applying a delta lays the archive out like `ArchiveBuilder`, so the target is the updated archive laid out that way.
+        // Its header has an unknown value other than the usual 1, which the delta has to carry over.
+        let updated_bytes = edited(updated.bytes, |layout| layout.header.unknown = 3);
+        let mut output = Cursor::new(Vec::new());
+        subset(&mut SgaArchive::from_reader(Cursor::new(updated_bytes)).unwrap(), &mut output, &ExtractOptions::default()).unwrap();
+        let target = output.into_inner();
+        assert_eq!(SgaArchive::from_reader(Cursor::new(target.clone())).unwrap().layout().unwrap().header.unknown, 3);
+
+        let mut output = Cursor::new(Vec::new());
+        let mut old_archive = SgaArchive::from_reader(Cursor::new(old.bytes.clone())).unwrap();
+        let created = create_delta(&mut old_archive, &mut SgaArchive::from_reader(Cursor::new(target.clone())).unwrap(), &mut output).unwrap();
+        let delta = output.into_inner();
+        assert_eq!((created.changed, created.added, created.removed, created.unchanged), (1, 1, 2, 5));
+
+        let mut output = Cursor::new(Vec::new());
+        let mut delta_archive = SgaArchive::from_reader(Cursor::new(delta.clone())).unwrap();
+        let applied = apply_delta(&mut old_archive, &mut delta_archive, &mut output).unwrap();
+        assert_eq!((applied.changed, applied.added, applied.removed, applied.unchanged), (1, 1, 2, 5));
+        assert_eq!(output.into_inner(), target);
+        assert_eq!(decoded(&target), updated.files);
+    }
+}
+
+#[test]
+fn applying_a_delta_to_another_archive_fails() {
+    let old = generate(TOCS, variants()[0]);
+    let updated = generate(UPDATED, variants()[0]);
+
+    let mut output = Cursor::new(Vec::new());
+    let mut old_archive = SgaArchive::from_reader(Cursor::new(old.bytes.clone())).unwrap();
+    create_delta(&mut old_archive, &mut SgaArchive::from_reader(Cursor::new(updated.bytes.clone())).unwrap(), &mut output).unwrap();
+    let delta = output.into_inner();
+
+    // Another layout of the same files, a version with one file changed, and the target itself.
+    let changed_file = edited(old.bytes, |layout| layout.files[0].crc ^= 1);
+    for base in [generate(TOCS, variants()[2]).bytes, changed_file, updated.bytes] {
+        let mut output = Cursor::new(Vec::new());
+        let mut delta_archive = SgaArchive::from_reader(Cursor::new(delta.clone())).unwrap();
+        let result = apply_delta(&mut SgaArchive::from_reader(Cursor::new(base)).unwrap(), &mut delta_archive, &mut output);
+        assert!(result.is_err());
+        assert!(output.into_inner().is_empty());
+    }
+}
+
+#[test]
+fn deltas_compare_the_data_of_files_without_a_verified_crc() {
+    // Both versions have the same CRC in every entry, which isn't checked as the files aren't verified with it.
+    let unverified = |tocs| {
+        edited(generate(tocs, variants()[0]).bytes, |layout| {
+            for entry in &mut layout.files {
+                entry.verification_type = FileVerificationType::None;
+                entry.crc = 0;
+            }
+        })
+    };
+    let old = unverified(&[("data", &[("a.txt", b"first"), ("b.txt", b"same")])]);
+    let new = unverified(&[("data", &[("a.txt", b"other"), ("b.txt", b"same")])]);
+
+    let mut output = Cursor::new(Vec::new());
+    let mut old_archive = SgaArchive::from_reader(Cursor::new(old)).unwrap();
+    let created = create_delta(&mut old_archive, &mut SgaArchive::from_reader(Cursor::new(new.clone())).unwrap(), &mut output).unwrap();
+    assert_eq!((created.changed, created.unchanged), (1, 1));
+
+    let mut applied = Cursor::new(Vec::new());
+    let mut delta_archive = SgaArchive::from_reader(Cursor::new(output.into_inner())).unwrap();
+    apply_delta(&mut old_archive, &mut delta_archive, &mut applied).unwrap();
+    assert_eq!(decoded(&applied.into_inner()), decoded(&new));
+}
+
+#[test]
+fn linked_files_have_the_contents_of_the_file_they_share_data_with() {
+    // Only the two icons share their data.
+    let generated = generate(TOCS, variants()[1]);
+    let (icon, copy) = ("data/art/ui/icon.txt", "data/art/ui/copy.txt");
+
+    for mode in [LinkMode::Hardlink, LinkMode::Reflink] {
+        for atomic in [AtomicMode::None, AtomicMode::PerFile] {
+            let folder = temp_folder(&format!("link-{:?}-{:?}", mode, atomic));
+            let mut archive = SgaArchive::from_reader(Cursor::new(generated.bytes.clone())).unwrap();
+            let options = ExtractOptions::new(&folder).toc_layout(TocLayout::Subdirectory).link_shared(mode).atomic(atomic);
+            let report = archive.extract(&options).unwrap();
+
+            assert_eq!(report.linked, 1);
+            assert_eq!(read_tree(&folder), generated.files);
+
+            // Writing into a hard link changes both files, while a reflink or copy is a file of its own.
+            fs::write(folder.join(copy), b"changed").unwrap();
+            let expected: &[u8] = match mode {
+                LinkMode::Hardlink => b"changed",
+                _ => &generated.files[icon],
+            };
+            assert_eq!(fs::read(folder.join(icon)).unwrap(), expected);
+
+            fs::remove_dir_all(&folder).unwrap();
+        }
+    }
+}
+
+#[test]
+fn extracting_over_linked_files_writes_each_file_on_its_own() {
+    let linked = generate(TOCS, variants()[1]);
+    let data: Vec<(&str, &[u8])> = TOCS[0]
+        .1
+        .iter()
+        .map(|&(path, data)| match path {
+            "art/ui/copy.txt" => (path, &b"changed copy"[..]),
+            _ => (path, data),
+        })
+        .collect();
+    let changed = generate(&[("data", &data), TOCS[1]], variants()[0]);
+
+    for atomic in [AtomicMode::None, AtomicMode::PerFile] {
+        let folder = temp_folder(&format!("relink-{:?}", atomic));
+        let options = ExtractOptions::new(&folder).link_shared(LinkMode::Hardlink).atomic(atomic);
+        SgaArchive::from_reader(Cursor::new(linked.bytes.clone())).unwrap().extract(&options).unwrap();
+
+        let options = ExtractOptions::new(&folder).atomic(atomic);
+        SgaArchive::from_reader(Cursor::new(changed.bytes.clone())).unwrap().extract(&options).unwrap();
+        assert_eq!(read_tree(&folder), changed.files);
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
+
+#[test]
+fn sinks_which_cannot_link_write_shared_files_as_usual() {
+    let generated = generate(TOCS, variants()[1]);
+
+    let mut archive = SgaArchive::from_reader(Cursor::new(generated.bytes)).unwrap();
+    let mut sink = MemorySink::new();
+    let options = ExtractOptions::default().toc_layout(TocLayout::Subdirectory).link_shared(LinkMode::Hardlink);
+    let report = archive.extract_to_sink(&mut sink, &options).unwrap();
+
+    let files: BTreeMap<String, Vec<u8>> = sink.into_files().into_iter().map(|(path, data)| (path.to_string_lossy().replace('\\', "/"), data)).collect();
+    assert_eq!(report.linked, 0);
+    assert_eq!(files, generated.files);
+}
+
+#[test]
+fn each_setting_of_a_packed_file_comes_from_the_first_rule_setting_it() {
+    let folder = temp_folder("rules");
+    write_tree(
+        &folder,
+        &[
+            ("data", "art/big.dds", &[1; 1000]),
+            ("data", "art/small.dds", &[2; 10]),
+            ("data", "art/readme.txt", &[3; 100]),
+            ("data", "script.lua", &[4; 100]),
+            ("data", "top.txt", &[5; 100]),
+        ],
+    );
+
+    let options = PackOptions::new("rules")
+        .storage_type(FileStorageType::BufferCompress)
+        .rule(PackRule::new("**/*.dds").smaller_than(64).storage_type(FileStorageType::Store))
+        .rule(PackRule::new("art/**").storage_type(FileStorageType::BufferCompressBrotli).verification_type(FileVerificationType::SHA1Blocks))
+        .rule(PackRule::new("*.dds").storage_type(FileStorageType::StreamCompress).verification_type(FileVerificationType::MD5Blocks))
+        .rule(PackRule::new("*.LUA").storage_type(FileStorageType::Store).level(1));
+    let mut bytes = Cursor::new(Vec::new());
+    pack_folder(&folder, &mut bytes, &options).unwrap();
+    let packed = bytes.into_inner();
+    fs::remove_dir_all(&folder).unwrap();
+
+    let expected = [
+        ("data/art/big.dds", FileStorageType::BufferCompressBrotli, FileVerificationType::SHA1Blocks),
+        ("data/art/readme.txt", FileStorageType::BufferCompressBrotli, FileVerificationType::SHA1Blocks),
+        ("data/art/small.dds", FileStorageType::Store, FileVerificationType::SHA1Blocks),
+        ("data/script.lua", FileStorageType::Store, FileVerificationType::CRC),
+        ("data/top.txt", FileStorageType::BufferCompress, FileVerificationType::CRC),
+    ];
+    let storage: BTreeMap<String, FileStorageType> = expected.iter().map(|(path, storage_type, _)| (path.to_string(), *storage_type)).collect();
+    let verification: BTreeMap<String, FileVerificationType> =
+        expected.iter().map(|(path, _, verification_type)| (path.to_string(), *verification_type)).collect();
+    assert_eq!(storage_types(&packed), storage);
+    assert_eq!(verification_types(&packed), verification);
+    assert_eq!(decoded(&packed).len(), expected.len());
+}
+
+#[test]
+fn rules_take_precedence_over_the_manifest_and_the_manifest_over_the_defaults() {
+    let generated = generate(TOCS, variants()[0]);
+    let folder = temp_folder("rules-manifest");
+    let mut archive = SgaArchive::from_reader(Cursor::new(generated.bytes.clone())).unwrap();
+    archive.extract(&ExtractOptions::new(&folder).manifest(ManifestFormat::Toml)).unwrap();
+    write_tree(&folder, &[("data", "extra.txt", b"not in the manifest")]);
+
+    let options = PackOptions::new("repacked")
+        .storage_type(FileStorageType::BufferCompressBrotli)
+        .rule(PackRule::new("sound/**").storage_type(FileStorageType::Store));
+    let mut bytes = Cursor::new(Vec::new());
+    pack_folder(&folder, &mut bytes, &options).unwrap();
+    fs::remove_dir_all(&folder).unwrap();
+
+    let mut expected = storage_types(&generated.bytes);
+    expected.insert("data/sound/voice.bin".to_string(), FileStorageType::Store);
+    expected.insert("data/extra.txt".to_string(), FileStorageType::BufferCompressBrotli);
+    assert_eq!(storage_types(&bytes.into_inner()), expected);
+}
+
+#[test]
+fn profiles_take_the_most_common_settings_of_each_extension_from_an_archive() {
+    let folder = temp_folder("profile");
+    let text = b"profile profile profile profile profile";
+    write_tree(
+        &folder,
+        &[
+            ("data", "a.txt", text),
+            ("data", "b.txt", text),
+            ("data", "sub/c.txt", text),
+            ("data", "d.LUA", text),
+            ("data", "e.rgd", text),
+            ("attrib", "f.rgd", text),
+            ("attrib", "no_extension", text),
+        ],
+    );
+
+    // `c.txt` is stored unlike most `.txt` files, and the `.rgd` files are only verified differently.
+    let config = PackConfig {
+        storage_type: FileStorageType::BufferCompress,
+        level: None,
+        verification_type: FileVerificationType::CRC,
+        rules: vec![
+            PackRule::new("sub/c.txt").storage_type(FileStorageType::Store),
+            PackRule::new("*.lua").storage_type(FileStorageType::Store),
+            PackRule::new("*.rgd").verification_type(FileVerificationType::MD5Blocks),
+        ],
+    };
+    let mut bytes = Cursor::new(Vec::new());
+    pack_folder(&folder, &mut bytes, &PackOptions::new("official").config(config)).unwrap();
+    fs::remove_dir_all(&folder).unwrap();
+
+    let archive = SgaArchive::from_reader(Cursor::new(bytes.into_inner())).unwrap();
+    let profile = PackConfig::from_archive(&archive).unwrap();
+    assert_eq!(
+        profile,
+        PackConfig {
+            storage_type: FileStorageType::BufferCompress,
+            level: None,
+            verification_type: FileVerificationType::CRC,
+            rules: vec![
+                PackRule::new("*.lua").storage_type(FileStorageType::Store).verification_type(FileVerificationType::CRC),
+                PackRule::new("*.rgd").storage_type(FileStorageType::BufferCompress).verification_type(FileVerificationType::MD5Blocks),
+            ],
+        }
+    );
+
+    let empty = SgaArchive::from_reader(Cursor::new(generate(&[("data", &[])], variants()[0]).bytes)).unwrap();
+    assert_eq!(PackConfig::from_archive(&empty).unwrap(), PackConfig::default());
+}
+
+#[test]
+fn pack_configs_parse_and_reject_what_they_do_not_know() {
+    let toml = r#"
+        storage_type = "BufferCompress"
+
+        [[rules]]
+        pattern = "*.lua"
+        smaller_than = 512
+        storage_type = "Store"
+        verification_type = "None"
+    "#;
+    let expected = PackConfig {
+        storage_type: FileStorageType::BufferCompress,
+        rules: vec![PackRule::new("*.lua").smaller_than(512).storage_type(FileStorageType::Store).verification_type(FileVerificationType::None)],
+        ..PackConfig::default()
+    };
+    assert_eq!(PackConfig::parse(toml, ManifestFormat::Toml).unwrap(), expected);
+    let json = serde_json::to_string(&expected).unwrap();
+    assert_eq!(PackConfig::parse(&json, ManifestFormat::Json).unwrap(), expected);
+    assert_eq!(PackConfig::parse("", ManifestFormat::Toml).unwrap(), PackConfig::default());
+
+    for (text, format) in [
+        (r#"storage_type = "Zip""#, ManifestFormat::Toml),
+        (r#"storage = "Store""#, ManifestFormat::Toml),
+        ("[[rules]]\nstorage_type = \"Store\"", ManifestFormat::Toml),
+        ("[[rules]]\npattern = \"*.lua\"\nstorage = \"Store\"", ManifestFormat::Toml),
+        (r#"{"verification_type": "CRC32"}"#, ManifestFormat::Json),
+        (r#"{"rules": [{"pattern": "*.lua", "smaller_than": -1}]}"#, ManifestFormat::Json),
+    ] {
+        assert!(PackConfig::parse(text, format).is_err(), "{}", text);
+    }
+
+    let folder = temp_folder("bad-pattern");
+    write_tree(&folder, &[("data", "a.txt", b"a")]);
+    let error = pack_folder(&folder, Cursor::new(Vec::new()), &PackOptions::new("bad").rule(PackRule::new("art/[")));
+    fs::remove_dir_all(&folder).unwrap();
+    assert!(error.unwrap_err().to_string().contains("art/["));
+}
+
+/// A file much larger than one read, so it is written in several chunks.
+const LARGE_FILE: &[(&str, TocFiles)] = &[("data", &[("large.bin", &[5; 100_000]), ("small.txt", b"small")])];
+
+/// Cancels an extraction as soon as it starts on the file with the name `name`.
+struct CancelOnStart {
+    token: CancellationToken,
+    name: &'static str,
+}
+
+impl ExtractObserver for CancelOnStart {
+    fn file_started(&self, path: &Path, _size: u64) {
+        if path.ends_with(self.name) {
+            self.token.cancel();
+        }
+    }
+}
+
+/// Writes into a folder, cancelling an extraction once the first chunk of a file has been read.
+struct CancelMidStream {
+    inner: DirectorySink,
+    token: CancellationToken,
+}
+
+struct CancelAfterRead<'a> {
+    data: &'a mut dyn Read,
+    token: &'a CancellationToken,
+}
+
+impl Read for CancelAfterRead<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.data.read(buf)?;
+        self.token.cancel();
+        Ok(read)
+    }
+}
+
+impl ExtractSink for CancelMidStream {
+    fn create_dir(&mut self, path: &Path) -> io::Result<()> {
+        self.inner.create_dir(path)
+    }
+
+    fn write_file(&mut self, path: &Path, size: u64, data: &mut dyn Read) -> io::Result<u64> {
+        let mut data = CancelAfterRead { data, token: &self.token };
+        self.inner.write_file(path, size, &mut data)
+    }
+
+    fn remove_file(&mut self, path: &Path) -> io::Result<()> {
+        self.inner.remove_file(path)
+    }
+}
+
+#[test]
+fn cancelling_while_writing_a_file_removes_it_if_asked() {
+    let generated = generate(LARGE_FILE, variants()[1]);
+    let large = Path::new("data").join("large.bin");
+
+    for remove_partial_files in [true, false] {
+        // Cancelled once the large file is started, before any of it is read.
+        let folder = temp_folder(&format!("cancel-start-{}", remove_partial_files));
+        let token = CancellationToken::new();
+        let observer = Arc::new(CancelOnStart { token: token.clone(), name: "large.bin" });
+        let options = ExtractOptions::new(&folder)
+            .toc_layout(TocLayout::Subdirectory)
+            .cancellation(token)
+            .remove_partial_files(remove_partial_files)
+            .observer(observer);
+
+        let result = SgaArchive::from_reader(Cursor::new(generated.bytes.clone())).unwrap().extract(&options);
+        assert!(Cancelled::is_cause_of(&result.unwrap_err()));
+        assert!(!folder.join("data/small.txt").exists());
+        assert_eq!(folder.join(&large).exists(), !remove_partial_files);
+        fs::remove_dir_all(&folder).unwrap();
+
+        // Cancelled after the first chunk of every file, which fails while the large file is read.
+        let folder = temp_folder(&format!("cancel-mid-stream-{}", remove_partial_files));
+        let token = CancellationToken::new();
+        let mut sink = CancelMidStream { inner: DirectorySink::new(&folder), token: token.clone() };
+        let options = ExtractOptions::new(&folder)
+            .toc_layout(TocLayout::Subdirectory)
+            .cancellation(token)
+            .remove_partial_files(remove_partial_files);
+
+        let mut archive = SgaArchive::from_reader(Cursor::new(generated.bytes.clone())).unwrap();
+        let result = archive.extract_to_sink(&mut sink, &options);
+        assert!(Cancelled::is_cause_of(&result.unwrap_err()));
+        match remove_partial_files {
+            true => assert!(!folder.join(&large).exists()),
+            false => {
+                let partial = fs::read(folder.join(&large)).unwrap();
+                assert!(!partial.is_empty() && partial.len() < 100_000);
+            }
+        }
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
+
+/// Folders nested a few levels deep, with a file name used at several levels.
+const NESTED: &[(&str, TocFiles)] = &[
+    (
+        "data",
+        &[
+            ("top.txt", b"top"),
+            ("art/readme.txt", b"art"),
+            ("art/ui/icon.txt", b"icon"),
+            ("art/ui/deep/readme.txt", b"deep"),
+        ],
+    ),
+    ("attrib", &[("a.rgd", b"a")]),
+];
+
+/// Extracts `bytes` into memory, returning the contents of every file keyed by its path separated by `/`.
+fn extract_to_memory(bytes: &[u8], options: &ExtractOptions) -> anyhow::Result<BTreeMap<String, Vec<u8>>> {
+    let mut archive = SgaArchive::from_reader(Cursor::new(bytes.to_vec()))?;
+    let mut sink = MemorySink::new();
+    archive.extract_to_sink(&mut sink, options)?;
+
+    Ok(sink.into_files().into_iter().map(|(path, data)| (slashed(&path), data)).collect())
+}
+
+fn tree(files: &[(&str, &[u8])]) -> BTreeMap<String, Vec<u8>> {
+    files.iter().map(|(path, data)| (path.to_string(), data.to_vec())).collect()
+}
+
+#[test]
+fn subtrees_are_extracted_to_their_path_up_to_the_maximum_depth() {
+    let bytes = generate(NESTED, variants()[0]).bytes;
+
+    // Tables of contents without the subtree are left out.
+    let options = ExtractOptions::default().subtree("art/ui");
+    let expected = tree(&[("data/art/ui/icon.txt", b"icon"), ("data/art/ui/deep/readme.txt", b"deep")]);
+    assert_eq!(extract_to_memory(&bytes, &options).unwrap(), expected);
+
+    let options = ExtractOptions::default().subtree("art").max_depth(0);
+    assert_eq!(extract_to_memory(&bytes, &options).unwrap(), tree(&[("data/art/readme.txt", b"art")]));
+
+    let options = ExtractOptions::default().max_depth(1);
+    let expected = tree(&[("data/top.txt", b"top"), ("data/art/readme.txt", b"art"), ("attrib/a.rgd", b"a")]);
+    assert_eq!(extract_to_memory(&bytes, &options).unwrap(), expected);
+
+    let err = extract_to_memory(&bytes, &ExtractOptions::default().subtree("art/missing")).unwrap_err();
+    assert_eq!(err.to_string(), "The folder 'art/missing' does not exist in the selected tables of contents");
+}
+
+#[test]
+fn flattened_files_with_the_same_name_conflict() {
+    let bytes = generate(NESTED, variants()[0]).bytes;
+
+    let options = ExtractOptions::default().subtree("art").flatten(true).overwrite(OverwritePolicy::Rename);
+    let expected = tree(&[("data/readme.txt", b"art"), ("data/icon.txt", b"icon"), ("data/readme (1).txt", b"deep")]);
+    assert_eq!(extract_to_memory(&bytes, &options).unwrap(), expected);
+
+    let options = ExtractOptions::default().subtree("art").flatten(true).max_depth(1);
+    assert_eq!(extract_to_memory(&bytes, &options).unwrap(), tree(&[("data/readme.txt", b"art"), ("data/icon.txt", b"icon")]));
+
+    let options = ExtractOptions::default().subtree("art").flatten(true).overwrite(OverwritePolicy::Error);
+    assert!(extract_to_memory(&bytes, &options).is_err());
+}
+
+#[test]
+fn extracting_toc_folders_only_writes_the_files_at_the_root_of_every_table_of_contents() {
+    let folder = temp_folder("toc-folders-only");
+    fs::create_dir_all(&folder).unwrap();
+    let archive_path = folder.join("archive.sga");
+    fs::write(&archive_path, generate(NESTED, variants()[0]).bytes).unwrap();
+
+    let output = folder.join("output");
+    sga::extract_toc_folders_only(&archive_path, &output).unwrap();
+    let files = read_tree(&output);
+    let empty_folders = ["art", "art/ui"].map(|path| output.join(path).is_dir());
+    fs::remove_dir_all(&folder).unwrap();
+
+    assert_eq!(files, tree(&[("top.txt", b"top"), ("a.rgd", b"a")]));
+    assert_eq!(empty_folders, [false, false]);
+}
+
+#[test]
+fn extracting_all_merges_every_table_of_contents_into_the_output() {
+    let folder = temp_folder("extract-all");
+    fs::create_dir_all(&folder).unwrap();
+    let archive_path = folder.join("archive.sga");
+    fs::write(&archive_path, generate(NESTED, variants()[2]).bytes).unwrap();
+
+    let output = folder.join("output");
+    sga::extract_all(&archive_path, &output).unwrap();
+    let files = read_tree(&output);
+    fs::remove_dir_all(&folder).unwrap();
+
+    let expected = tree(&[
+        ("top.txt", b"top"),
+        ("art/readme.txt", b"art"),
+        ("art/ui/icon.txt", b"icon"),
+        ("art/ui/deep/readme.txt", b"deep"),
+        ("a.rgd", b"a"),
+    ]);
+    assert_eq!(files, expected);
+}
+
+/// The names of the files and folders next to `output`, such as leftover staging folders.
+fn siblings(output: &Path) -> Vec<String> {
+    let mut names: Vec<_> = fs::read_dir(output.parent().unwrap())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| Path::new(name) != output.file_name().unwrap())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn staged_extractions_replace_the_output_only_once_they_succeed() {
+    let generated = generate(TOCS, variants()[0]);
+    let folder = temp_folder("staged");
+    let output = folder.join("output");
+    let old = BTreeMap::from([("old.txt".to_string(), b"old".to_vec()), ("data/top.txt".to_string(), b"old top".to_vec())]);
+    let restore_old = || {
+        let _ = fs::remove_dir_all(&output);
+        fs::create_dir_all(output.join("data")).unwrap();
+        for (path, data) in &old {
+            fs::write(output.join(path), data).unwrap();
+        }
+    };
+    let options = ExtractOptions::new(&output).atomic(AtomicMode::Staged);
+
+    // Nothing of the previous output is kept.
+    restore_old();
+    SgaArchive::from_reader(Cursor::new(generated.bytes.clone())).unwrap().extract(&options).unwrap();
+    assert_eq!(read_tree(&output), generated.files);
+    assert!(siblings(&output).is_empty());
+
+    // A file failing its CRC check.
+    restore_old();
+    let corrupted = edited(generated.bytes.clone(), |layout| layout.files[0].crc ^= 1);
+    let result = SgaArchive::from_reader(Cursor::new(corrupted)).unwrap().extract(&options.clone().verification(VerificationLevel::Crc));
+    assert!(result.is_err());
+    assert_eq!(read_tree(&output), old);
+    assert!(siblings(&output).is_empty());
+
+    // Cancelling half way through.
+    restore_old();
+    let token = CancellationToken::new();
+    let observer = Arc::new(CancelAfter { token: token.clone(), remaining: Mutex::new(3) });
+    let cancelled = options.clone().cancellation(token).observer(observer);
+    let result = SgaArchive::from_reader(Cursor::new(generated.bytes.clone())).unwrap().extract(&cancelled);
+    assert!(Cancelled::is_cause_of(&result.unwrap_err()));
+    assert_eq!(read_tree(&output), old);
+    assert!(siblings(&output).is_empty());
+
+    // Staged extractions always start over.
+    let result = SgaArchive::from_reader(Cursor::new(generated.bytes)).unwrap().extract(&options.clone().resume(true));
+    assert_eq!(result.unwrap_err().to_string(), "A staged extraction can not be resumed, it always starts over");
+    assert_eq!(read_tree(&output), old);
+    assert!(siblings(&output).is_empty());
+
+    fs::remove_dir_all(&folder).unwrap();
+}
+
+/// The folders and files `bytes` is extracted into, in the order they're written.
+fn planned_paths(bytes: &[u8]) -> (Vec<String>, Vec<String>) {
+    let archive = SgaArchive::from_reader(Cursor::new(bytes.to_vec())).unwrap();
+    let plan = archive.plan(&ExtractOptions::default()).unwrap();
+
+    (plan.folders.iter().map(|path| slashed(path)).collect(), plan.files.iter().map(|planned| slashed(&planned.path)).collect())
+}
+
+#[test]
+fn patching_sorts_the_entries_and_leaves_out_empty_folders() {
+    // The archive gets an empty folder `attrib/empty`, next to the unsorted `icon.txt` and `copy.txt`.
+    let bytes = edited(generate(TOCS, variants()[0]).bytes, |layout| {
+        let name_offset = layout.string_blob().len() as u32;
+        layout.strings.push("empty".to_string());
+
+        let index = layout.folders.len() as u32;
+        let toc = &mut layout.tocs[1];
+        toc.folder_end_index = index + 1;
+        let root = &mut layout.folders[toc.folder_root_index as usize];
+        (root.folder_start_index, root.folder_end_index) = (index, index + 1);
+        let files = toc.file_end_index;
+        layout.folders.push(SgaFolderEntry { name_offset, folder_start_index: 0, folder_end_index: 0, file_start_index: files, file_end_index: files });
+        layout.arrange_header_blob().unwrap();
+    });
+    let (folders, files) = planned_paths(&bytes);
+    assert!(folders.contains(&"attrib/empty".to_string()));
+    assert!(files.iter().position(|path| path.ends_with("icon.txt")) < files.iter().position(|path| path.ends_with("copy.txt")));
+
+    let folder = temp_folder("patch-order");
+    fs::create_dir_all(&folder).unwrap();
+    let path = folder.join("archive.sga");
+    fs::write(&path, &bytes).unwrap();
+    ArchivePatcher::open(&path).unwrap().commit().unwrap();
+    let patched = fs::read(&path).unwrap();
+    fs::remove_dir_all(&folder).unwrap();
+
+    let (folders, files) = planned_paths(&patched);
+    assert_eq!(folders, ["data/", "data/art", "data/art/ui", "data/art/ui/deep", "data/sound", "attrib/"]);
+    assert_eq!(
+        files,
+        [
+            "data/top.txt",
+            "data/art/readme.txt",
+            "data/art/ui/copy.txt",
+            "data/art/ui/icon.txt",
+            "data/art/ui/deep/empty.bin",
+            "data/sound/voice.bin",
+            "attrib/a.rgd",
+            "attrib/b.txt",
+        ]
+    );
+    assert_eq!(decoded(&patched), decoded(&bytes));
+}
+
+#[test]
+fn packing_stores_identical_files_once() {
+    let shared = [3; 500];
+    let folder = temp_folder("pack-shared");
+    write_tree(&folder, &[("data", "a.bin", &shared), ("data", "b.txt", b"other"), ("data", "sub/c.bin", &shared)]);
+
+    let options = PackOptions::new("packed").storage_type(FileStorageType::Store);
+    let mut bytes = Cursor::new(Vec::new());
+    let report = pack_folder(&folder, &mut bytes, &options).unwrap();
+    fs::remove_dir_all(&folder).unwrap();
+    let bytes = bytes.into_inner();
+
+    let mut archive = SgaArchive::from_reader(Cursor::new(bytes.clone())).unwrap();
+    let layout = archive.layout().unwrap();
+    let offsets: Vec<_> = layout.files.iter().filter(|entry| entry.compressed_length == 500).map(|entry| entry.data_offset).collect();
+    assert_eq!(offsets.len(), 2);
+    assert_eq!(offsets[0], offsets[1]);
+    assert_eq!(layout.header.data_blob_length, 505);
+    assert_eq!((report.stored_bytes, report.shared_bytes), (505, 500));
+    assert_eq!(decoded(&bytes)["data/sub/c.bin"], shared);
+}